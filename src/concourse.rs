@@ -1,6 +1,9 @@
-use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::BufRead;
 
-#[derive(PartialEq, Deserialize, Debug)]
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+#[derive(PartialEq, Serialize, Deserialize, Debug, Clone)]
 #[serde(rename_all = "snake_case")]
 pub(crate) enum Status {
     Started,
@@ -11,17 +14,35 @@ pub(crate) enum Status {
     Aborted,
 }
 
-#[derive(Deserialize, Debug)]
+impl Status {
+    /// Whether a build with this status ran to completion, aborted builds are not considered.
+    pub(crate) fn is_completed(&self) -> bool {
+        matches!(self, Status::Succeeded | Status::Failed | Status::Errored)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub(crate) struct Build {
+    pub(crate) id: u32,
+    pub(crate) team_name: String,
+    pub(crate) name: String,
     pub(crate) status: Option<Status>,
-    // id: u32,
-    // team_name: String,
-    // name: String,
-    // job_name: String,
-    // api_url: String,
-    // pipeline_name: String,
-    // start_time: u64,
-    // end_time: u64,
+    pub(crate) job_name: Option<String>,
+    pub(crate) pipeline_name: Option<String>,
+    pub(crate) api_url: String,
+    /// Unix timestamp, in seconds
+    pub(crate) start_time: Option<i64>,
+    /// Unix timestamp, in seconds
+    pub(crate) end_time: Option<i64>,
+    pub(crate) created_by: Option<String>,
+    pub(crate) rerun_number: Option<u32>,
+    pub(crate) rerun_of: Option<RerunOf>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub(crate) struct RerunOf {
+    pub(crate) id: u32,
+    pub(crate) name: String,
 }
 
 pub(crate) struct Concourse {
@@ -139,45 +160,630 @@ impl Concourse {
         self
     }
 
-    pub(crate) fn get_build(
-        self,
+    pub(crate) fn get_build(&self, build: u32, debug: bool) -> Option<Build> {
+        self.get_json(&format!("{}api/v1/builds/{}", self.url, build), debug)
+    }
+
+    /// Find the step that failed in a build, with the end of its logs, from the build events.
+    pub(crate) fn failed_step(
+        &self,
+        build: u32,
+        log_lines: usize,
+        debug: bool,
+    ) -> Option<FailedStep> {
+        let mut names = HashMap::new();
+        if let Some(plan) = self.get_json::<serde_json::Value>(
+            &format!("{}api/v1/builds/{}/plan", self.url, build),
+            debug,
+        ) {
+            step_names(&plan, &mut names);
+        }
+
+        // the build is still running this step, so the stream will not end by itself
+        let response = self.send(
+            self.request(
+                &format!("{}api/v1/builds/{}/events", self.url, build),
+                debug,
+            )?
+            .timeout(std::time::Duration::from_secs(EVENTS_TIMEOUT_SECONDS)),
+            debug,
+        )?;
+        let mut finder = FailedStepFinder::new(log_lines);
+        parse_events(std::io::BufReader::new(response), |event| {
+            finder.push(event)
+        });
+
+        let failed_step = finder.failed_step(&names);
+        if failed_step.is_none() && debug {
+            eprintln!(
+                "no failed step in {} build events, the stream ended or timed out after {}s",
+                finder.events, EVENTS_TIMEOUT_SECONDS
+            );
+        }
+        failed_step
+    }
+
+    /// GET `url` and deserialize its JSON body.
+    fn get_json<T: DeserializeOwned>(&self, url: &str, debug: bool) -> Option<T> {
+        self.send(self.request(url, debug)?, debug)?
+            .json::<T>()
+            .map_err(|err| {
+                if debug {
+                    eprintln!("got an error deserializing: {:}", err)
+                }
+            })
+            .ok()
+    }
+
+    /// A GET request to `url`, with the token of the client.
+    fn request(&self, url: &str, debug: bool) -> Option<reqwest::blocking::RequestBuilder> {
+        let url = reqwest::Url::parse(url).ok()?;
+        if debug {
+            eprintln!("getting url {:?}", url);
+        }
+        let req = self
+            .client
+            .as_ref()
+            .expect("error configuring HTTP client")
+            .get(url);
+        Some(match self.bearer.as_ref() {
+            Some(token) => req.bearer_auth(token),
+            None => req,
+        })
+    }
+
+    fn send(
+        &self,
+        req: reqwest::blocking::RequestBuilder,
+        debug: bool,
+    ) -> Option<reqwest::blocking::Response> {
+        req.send()
+            .map(|response| {
+                if debug {
+                    eprintln!("response: {:?}", response.status());
+                }
+                response
+            })
+            .map_err(|err| {
+                if debug {
+                    eprintln!("got an error getting url: {:?}", err);
+                }
+            })
+            .ok()
+    }
+
+    /// Find the latest completed build of a job before the build `build_id`.
+    pub(crate) fn previous_build(
+        &self,
         team: &str,
         pipeline: &str,
         job: &str,
-        build: u32,
+        build_id: u32,
         debug: bool,
     ) -> Option<Build> {
-        reqwest::Url::parse(&format!(
-            "{}api/v1/teams/{}/pipelines/{}/jobs/{}/builds/{}",
-            self.url, team, pipeline, job, build
+        self.completed_builds_before(team, pipeline, job, build_id, |_| true, debug)
+            .and_then(|(mut builds, _)| builds.pop())
+    }
+
+    /// Count the consecutive failed or errored builds of a job, including the build `build_id`
+    /// named `build_name`, and find the latest completed build before it on the way. Returns `None`
+    /// if the builds of the job could not be listed.
+    pub(crate) fn failing_streak(
+        &self,
+        team: &str,
+        pipeline: &str,
+        job: &str,
+        build_id: u32,
+        build_name: &str,
+        debug: bool,
+    ) -> Option<(Streak, Option<Build>)> {
+        let (mut builds, truncated) = self.completed_builds_before(
+            team,
+            pipeline,
+            job,
+            build_id,
+            |build| build.status == Some(Status::Succeeded),
+            debug,
+        )?;
+        let previous_build = builds.first().cloned();
+        if builds
+            .last()
+            .map(|build| build.status == Some(Status::Succeeded))
+            .unwrap_or(false)
+        {
+            builds.pop();
+        }
+        Some((
+            Streak {
+                count: builds.len() + 1,
+                since: builds
+                    .last()
+                    .map(|build| build.name.clone())
+                    .unwrap_or_else(|| String::from(build_name)),
+                truncated,
+            },
+            previous_build,
         ))
-        .map_err(|_| ())
-        .and_then(|url| {
-            if debug {
-                eprintln!("getting url {:?}", url);
+    }
+
+    /// Walk the completed builds of a job before the build `build_id`, going through the pages of
+    /// the job builds, until `stop` is true for a build. The build that stopped the walk is the last
+    /// one returned, with whether the walk was cut short after `MAX_BUILDS_PAGES` pages. Returns
+    /// `None` if a page could not be read.
+    fn completed_builds_before<F: Fn(&Build) -> bool>(
+        &self,
+        team: &str,
+        pipeline: &str,
+        job: &str,
+        build_id: u32,
+        stop: F,
+        debug: bool,
+    ) -> Option<(Vec<Build>, bool)> {
+        let mut walked = vec![];
+        let mut url = Some(format!(
+            "{}api/v1/teams/{}/pipelines/{}/jobs/{}/builds?limit={}",
+            self.url,
+            urlencoding::encode(team),
+            urlencoding::encode(pipeline),
+            urlencoding::encode(job),
+            BUILDS_PAGE_SIZE
+        ));
+        let mut pages = 0;
+        while let Some(page_url) = url {
+            pages += 1;
+            let (builds, next) = self.get_builds(&page_url, debug)?;
+            if walk_completed_before(builds, build_id, &stop, &mut walked) {
+                return Some((walked, false));
             }
-            let mut req = self.client.expect("error configuring HTTP client").get(url);
-            if let Some(token) = self.bearer.as_ref() {
-                req = req.bearer_auth(token);
+            url = next.map(|next| {
+                if next.starts_with('/') {
+                    format!("{}{}", self.url, next.trim_start_matches('/'))
+                } else {
+                    next
+                }
+            });
+            if pages >= MAX_BUILDS_PAGES {
+                return Some((walked, url.is_some()));
             }
+        }
+        Some((walked, false))
+    }
 
-            req.send()
-                .map_err(|err| {
-                    if debug {
-                        eprintln!("got an error getting build: {:?}", err);
-                    }
-                })
-                .and_then(|req| {
-                    if debug {
-                        eprintln!("response: {:?}", req.status());
-                    }
-                    req.json::<Build>().map_err(|err| {
-                        if debug {
-                            eprintln!("got an error deserializing: {:}", err)
-                        }
-                    })
-                })
+    fn get_builds(&self, url: &str, debug: bool) -> Option<(Vec<Build>, Option<String>)> {
+        let response = self.send(self.request(url, debug)?, debug)?;
+        let next = response
+            .headers()
+            .get(reqwest::header::LINK)
+            .and_then(|link| link.to_str().ok())
+            .and_then(next_page);
+        response
+            .json::<Vec<Build>>()
+            .map(|builds| (builds, next))
+            .map_err(|err| {
+                if debug {
+                    eprintln!("got an error deserializing: {:}", err)
+                }
+            })
+            .ok()
+    }
+}
+
+const BUILDS_PAGE_SIZE: u32 = 100;
+const MAX_BUILDS_PAGES: u32 = 10;
+
+const EVENTS_TIMEOUT_SECONDS: u64 = 5;
+
+/// Consecutive failed builds of a job, up to the current build.
+#[derive(Serialize, Debug, PartialEq, Clone)]
+pub(crate) struct Streak {
+    pub(crate) count: usize,
+    /// Name of the first failed build
+    pub(crate) since: String,
+    /// The streak is longer than the builds that were walked, `since` is the oldest one
+    pub(crate) truncated: bool,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub(crate) struct FailedStep {
+    pub(crate) name: String,
+    pub(crate) log: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct Event {
+    event: String,
+    #[serde(default)]
+    data: serde_json::Value,
+}
+
+/// Read the events of a server-sent events stream until its end, or until it can't be read
+/// anymore.
+fn parse_events<R: BufRead, F: FnMut(Event)>(reader: R, mut on_event: F) {
+    for line in reader.lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => break,
+        };
+        if line == "event: end" {
+            break;
+        }
+        if let Some(data) = line.strip_prefix("data: ") {
+            if let Ok(event) = serde_json::from_str::<Event>(data) {
+                on_event(event);
+            }
+        }
+    }
+}
+
+/// Names of the steps of a build plan, by their ID.
+fn step_names(plan: &serde_json::Value, names: &mut HashMap<String, String>) {
+    match plan {
+        serde_json::Value::Object(object) => {
+            if let Some(id) = object.get("id").and_then(|id| id.as_str()) {
+                if let Some(name) = object
+                    .values()
+                    .find_map(|step| step.get("name").and_then(|name| name.as_str()))
+                {
+                    names.insert(String::from(id), String::from(name));
+                }
+            }
+            object.values().for_each(|value| step_names(value, names));
+        }
+        serde_json::Value::Array(array) => {
+            array.iter().for_each(|value| step_names(value, names));
+        }
+        _ => (),
+    }
+}
+
+/// Finds the step that failed in the events of a build, keeping only the end of the logs of each
+/// step while the events are read.
+struct FailedStepFinder {
+    log_lines: usize,
+    /// End of the logs of each step, by origin ID
+    logs: HashMap<String, String>,
+    /// Origin ID and name of the last step that failed
+    failed: Option<(String, Option<String>)>,
+    events: usize,
+}
+
+impl FailedStepFinder {
+    fn new(log_lines: usize) -> Self {
+        Self {
+            log_lines,
+            logs: HashMap::new(),
+            failed: None,
+            events: 0,
+        }
+    }
+
+    fn push(&mut self, event: Event) {
+        self.events += 1;
+        let origin = event.data["origin"]["id"]
+            .as_str()
+            .map(String::from)
+            .unwrap_or_default();
+        let name = event.data["origin"]["name"].as_str().map(String::from);
+        match event.event.as_str() {
+            "log" => self.append_log(&origin, event.data["payload"].as_str().unwrap_or("")),
+            "error" => {
+                self.append_log(&origin, event.data["message"].as_str().unwrap_or(""));
+                self.append_log(&origin, "\n");
+                self.failed = Some((origin, name));
+            }
+            "finish-task" | "finish-get" | "finish-put"
+                if event.data["exit_status"].as_i64().unwrap_or(0) != 0 =>
+            {
+                self.failed = Some((origin, name));
+            }
+            _ => (),
+        }
+    }
+
+    /// Keep the last `log_lines` lines of the step, and the line it is still writing.
+    fn append_log(&mut self, origin: &str, text: &str) {
+        let log = self.logs.entry(String::from(origin)).or_default();
+        log.push_str(text);
+        let extra_lines = log.matches('\n').count().saturating_sub(self.log_lines);
+        if let Some((end, _)) = extra_lines
+            .checked_sub(1)
+            .and_then(|extra_line| log.match_indices('\n').nth(extra_line))
+        {
+            log.drain(..=end);
+        }
+    }
+
+    fn failed_step(&self, names: &HashMap<String, String>) -> Option<FailedStep> {
+        let (id, name) = self.failed.as_ref()?;
+        let log = strip_ansi(self.logs.get(id).map(String::as_str).unwrap_or(""));
+        let lines = log
+            .lines()
+            // progress bars rewrite the line after a carriage return
+            .map(|line| line.rsplit('\r').next().unwrap_or("").trim_end())
+            .collect::<Vec<_>>();
+        Some(FailedStep {
+            name: names
+                .get(id)
+                .or(name.as_ref())
+                .cloned()
+                .unwrap_or_else(|| id.clone()),
+            log: lines[lines.len().saturating_sub(self.log_lines)..]
+                .join("\n")
+                .trim_matches('\n')
+                .to_string(),
         })
-        .ok()
+    }
+}
+
+/// Remove ANSI escape sequences, used for colors in the logs.
+fn strip_ansi(text: &str) -> String {
+    let mut stripped = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '\u{1b}' {
+            if chars.peek() == Some(&'[') {
+                chars.next();
+                // parameters until the final byte of the sequence
+                for c in chars.by_ref() {
+                    if ('@'..='~').contains(&c) {
+                        break;
+                    }
+                }
+            } else {
+                chars.next();
+            }
+        } else {
+            stripped.push(c);
+        }
+    }
+    stripped
+}
+
+/// Builds are listed from the most recent, add the completed ones before `build_id` to `walked`
+/// until `stop` is true for one of them. Returns whether the walk stopped.
+fn walk_completed_before<F: Fn(&Build) -> bool>(
+    builds: Vec<Build>,
+    build_id: u32,
+    stop: F,
+    walked: &mut Vec<Build>,
+) -> bool {
+    for build in builds.into_iter().filter(|build| {
+        build.id < build_id
+            && build
+                .status
+                .as_ref()
+                .map(Status::is_completed)
+                .unwrap_or(false)
+    }) {
+        let stopped = stop(&build);
+        walked.push(build);
+        if stopped {
+            return true;
+        }
+    }
+    false
+}
+
+/// URL of the next page of results from a `Link` header.
+fn next_page(link: &str) -> Option<String> {
+    link.split(',').find_map(|link| {
+        let (url, rel) = link.split_once(';')?;
+        if rel.trim() == r#"rel="next""# {
+            Some(String::from(
+                url.trim().trim_start_matches('<').trim_end_matches('>'),
+            ))
+        } else {
+            None
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn can_find_previous_completed_build() {
+        let builds = serde_json::from_str::<Vec<Build>>(
+            r#"[
+                {"id": 12, "team_name": "main", "name": "7", "status": "started", "api_url": "/api/v1/builds/12"},
+                {"id": 11, "team_name": "main", "name": "6.1", "status": "succeeded", "api_url": "/api/v1/builds/11"},
+                {"id": 10, "team_name": "main", "name": "6", "status": "aborted", "api_url": "/api/v1/builds/10"},
+                {"id": 9, "team_name": "main", "name": "5", "status": "failed", "api_url": "/api/v1/builds/9"}
+            ]"#,
+        )
+        .unwrap();
+        let mut walked = vec![];
+        assert!(walk_completed_before(builds, 11, |_| true, &mut walked));
+        assert_eq!(walked.pop().map(|build| build.id), Some(9));
+    }
+
+    #[test]
+    fn can_walk_failing_builds() {
+        let builds = serde_json::from_str::<Vec<Build>>(
+            r#"[
+                {"id": 13, "team_name": "main", "name": "8", "status": "started", "api_url": "/api/v1/builds/13"},
+                {"id": 12, "team_name": "main", "name": "7", "status": "errored", "api_url": "/api/v1/builds/12"},
+                {"id": 11, "team_name": "main", "name": "6", "status": "aborted", "api_url": "/api/v1/builds/11"},
+                {"id": 10, "team_name": "main", "name": "5", "status": "failed", "api_url": "/api/v1/builds/10"}
+            ]"#,
+        )
+        .unwrap();
+        let mut walked = vec![];
+        assert!(!walk_completed_before(
+            builds,
+            13,
+            |build| build.status == Some(Status::Succeeded),
+            &mut walked
+        ));
+        assert_eq!(
+            walked.iter().map(|build| build.id).collect::<Vec<_>>(),
+            vec![12, 10]
+        );
+    }
+
+    #[test]
+    fn can_deserialize_build() {
+        let build = serde_json::from_str::<Build>(
+            r#"{
+                "id": 1234,
+                "team_name": "main",
+                "name": "6.1",
+                "status": "failed",
+                "job_name": "test",
+                "api_url": "/api/v1/builds/1234",
+                "pipeline_id": 1,
+                "pipeline_name": "ci",
+                "start_time": 1603960000,
+                "end_time": 1603960125,
+                "created_by": "someone",
+                "rerun_number": 1,
+                "rerun_of": {"id": 1200, "name": "6"}
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(build.status, Some(Status::Failed));
+        assert_eq!(build.end_time, Some(1603960125));
+        assert_eq!(build.created_by.as_deref(), Some("someone"));
+        assert_eq!(
+            build.rerun_of.map(|rerun_of| rerun_of.name).as_deref(),
+            Some("6")
+        );
+    }
+
+    #[test]
+    fn can_find_failed_step() {
+        let stream = r#"id: 0
+event: event
+data: {"data":{"time":1603960000,"origin":{"id":"5f2a1b3c"},"payload":"fetching\n"},"event":"log","version":"5.1"}
+
+id: 1
+event: event
+data: {"data":{"time":1603960001,"origin":{"id":"5f2a1b3d"},"payload":"\u001b[1mrunning tests\u001b[0m\nok 1\n"},"event":"log","version":"5.1"}
+
+id: 2
+event: event
+data: {"data":{"time":1603960002,"origin":{"id":"5f2a1b3d"},"payload":"ok 2\n\u001b[31mnot ok 3\u001b[0m\n"},"event":"log","version":"5.1"}
+
+id: 3
+event: event
+data: {"data":{"time":1603960003,"origin":{"id":"5f2a1b3d"},"exit_status":1},"event":"finish-task","version":"4.0"}
+
+event: end
+data
+
+"#;
+        let plan = serde_json::json!({
+            "schema": "exec.v2",
+            "plan": {
+                "id": "5f2a1b3e",
+                "do": [
+                    {"id": "5f2a1b3c", "get": {"name": "repo", "type": "git"}},
+                    {"id": "5f2a1b3d", "task": {"name": "unit-tests", "privileged": false}}
+                ]
+            }
+        });
+        let mut names = HashMap::new();
+        step_names(&plan, &mut names);
+
+        let mut finder = FailedStepFinder::new(2);
+        parse_events(stream.as_bytes(), |event| finder.push(event));
+        assert_eq!(finder.events, 4);
+        assert_eq!(finder.logs["5f2a1b3d"].matches('\n').count(), 2);
+        assert_eq!(
+            finder.failed_step(&names),
+            Some(FailedStep {
+                name: String::from("unit-tests"),
+                log: String::from("ok 2\nnot ok 3"),
+            })
+        );
+
+        let mut finder = FailedStepFinder::new(2);
+        parse_events(stream.as_bytes(), |event| {
+            if event.event != "finish-task" {
+                finder.push(event)
+            }
+        });
+        assert_eq!(finder.failed_step(&names), None);
+    }
+
+    #[test]
+    fn can_get_next_page() {
+        let link = r#"<https://ci.example.com/api/v1/teams/main/pipelines/p/jobs/j/builds?from=20&limit=100>; rel="previous", </api/v1/teams/main/pipelines/p/jobs/j/builds?to=9&limit=100>; rel="next""#;
+        assert_eq!(
+            next_page(link).as_deref(),
+            Some("/api/v1/teams/main/pipelines/p/jobs/j/builds?to=9&limit=100")
+        );
+        assert_eq!(
+            next_page(r#"<https://ci.example.com/>; rel="previous""#),
+            None
+        );
+    }
+
+    /// A minimal HTTP server answering `pages` requests with a page of one failed build linking to
+    /// the next page, then one request with an error if `error` is set.
+    fn builds_server(pages: usize, error: bool) -> (String, std::thread::JoinHandle<()>) {
+        use std::io::Write;
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let handle = std::thread::spawn(move || {
+            let requests = pages + usize::from(error);
+            for (page, stream) in listener.incoming().take(requests).enumerate() {
+                let mut stream = stream.unwrap();
+                let mut reader = std::io::BufReader::new(stream.try_clone().unwrap());
+                let mut line = String::new();
+                while reader.read_line(&mut line).unwrap() > 2 {
+                    line.clear();
+                }
+                if page >= pages {
+                    stream
+                        .write_all(b"HTTP/1.1 500 Internal Server Error\r\ncontent-length: 0\r\nconnection: close\r\n\r\n")
+                        .unwrap();
+                    return;
+                }
+                let body = format!(
+                    r#"[{{"id": {}, "team_name": "main", "name": "{}", "status": "failed", "api_url": "/api/v1/builds/{}"}}]"#,
+                    1000 - page,
+                    1000 - page,
+                    1000 - page
+                );
+                write!(
+                    stream,
+                    "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\nlink: </api/v1/builds?page={}>; rel=\"next\"\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                    page + 1,
+                    body.len(),
+                    body
+                )
+                .unwrap();
+            }
+        });
+        (url, handle)
+    }
+
+    #[test]
+    fn can_truncate_failing_streak() {
+        let (url, handle) = builds_server(MAX_BUILDS_PAGES as usize, false);
+        let streak = Concourse::new(&url)
+            .build()
+            .failing_streak("main", "ci", "unit", 1001, "1001", false);
+        handle.join().unwrap();
+        let (streak, previous_build) = streak.unwrap();
+        assert_eq!(
+            streak,
+            Streak {
+                count: 11,
+                since: String::from("991"),
+                truncated: true,
+            }
+        );
+        assert_eq!(previous_build.map(|build| build.id), Some(1000));
+
+        let (url, handle) = builds_server(2, true);
+        let streak = Concourse::new(&url)
+            .build()
+            .failing_streak("main", "ci", "unit", 1001, "1001", false);
+        handle.join().unwrap();
+        assert!(streak.is_none());
     }
 }
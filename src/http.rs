@@ -0,0 +1,257 @@
+use serde::Serialize;
+use std::time::Duration;
+
+const DEFAULT_RETRIES: u32 = 3;
+const DEFAULT_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_DELAY: Duration = Duration::from_secs(60);
+
+pub(crate) struct Client {
+    client: reqwest::blocking::Client,
+    retries: u32,
+    backoff: Duration,
+    debug: bool,
+}
+
+impl Client {
+    pub(crate) fn new() -> Self {
+        Self {
+            client: reqwest::blocking::Client::new(),
+            retries: DEFAULT_RETRIES,
+            backoff: DEFAULT_BACKOFF,
+            debug: false,
+        }
+    }
+
+    pub(crate) fn retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    pub(crate) fn backoff(mut self, backoff: Duration) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    pub(crate) fn debug(mut self, debug: bool) -> Self {
+        self.debug = debug;
+        self
+    }
+
+    /// POST `body` as JSON to `url`, see [`Client::send`].
+    pub(crate) fn post_json<T: Serialize>(
+        &self,
+        url: &str,
+        bearer: Option<&str>,
+        body: &T,
+    ) -> Result<String, String> {
+        let body = serde_json::to_vec(body).map_err(|err| format!("{}", err))?;
+        let authorization = bearer.map(|token| format!("Bearer {}", token));
+        let mut headers = vec![("Content-Type", "application/json")];
+        if let Some(authorization) = authorization.as_ref() {
+            headers.push(("Authorization", authorization));
+        }
+        self.send(reqwest::Method::POST, url, &headers, body)
+    }
+
+    /// Send `body` to `url`, retrying on connection errors, `429` and `5xx`.
+    /// Returns the body of the response, or an error with the status and body of the last attempt.
+    pub(crate) fn send(
+        &self,
+        method: reqwest::Method,
+        url: &str,
+        headers: &[(&str, &str)],
+        body: Vec<u8>,
+    ) -> Result<String, String> {
+        let url = reqwest::Url::parse(url).map_err(|err| format!("{}", err))?;
+        let mut attempt = 0;
+        loop {
+            let mut req = self
+                .client
+                .request(method.clone(), url.clone())
+                .body(body.clone());
+            for (name, value) in headers {
+                req = req.header(*name, *value);
+            }
+            let (error, retry_after) = match req.send() {
+                Ok(response) if response.status().is_success() => {
+                    return response.text().map_err(|err| format!("{}", err));
+                }
+                Ok(response) => {
+                    let status = response.status();
+                    let retry_after = response
+                        .headers()
+                        .get(reqwest::header::RETRY_AFTER)
+                        .and_then(|value| value.to_str().ok())
+                        .and_then(|value| value.trim().parse::<u64>().ok())
+                        .map(Duration::from_secs);
+                    let body = response.text().unwrap_or_default();
+                    let error = if body.trim().is_empty() {
+                        format!("{}", status)
+                    } else {
+                        format!("{} ({})", body.trim(), status)
+                    };
+                    if !(status == reqwest::StatusCode::TOO_MANY_REQUESTS
+                        || status.is_server_error())
+                    {
+                        return Err(error);
+                    }
+                    (error, retry_after)
+                }
+                Err(err) => (format!("{}", err), None),
+            };
+
+            if attempt >= self.retries {
+                return Err(error);
+            }
+            let delay = retry_after
+                .unwrap_or_else(|| delay_for_attempt(self.backoff, attempt))
+                .min(MAX_DELAY);
+            if self.debug {
+                eprintln!("error sending request: {}, retrying in {:?}", error, delay);
+            }
+            std::thread::sleep(delay);
+            attempt += 1;
+        }
+    }
+
+    /// Run `operation` with the retries and backoff of the client, for deliveries that are not
+    /// made over HTTP. `operation` returns whether its error can be retried.
+    pub(crate) fn retry<T, F: FnMut() -> Result<T, (String, bool)>>(
+        &self,
+        mut operation: F,
+    ) -> Result<T, String> {
+        let mut attempt = 0;
+        loop {
+            let error = match operation() {
+                Ok(value) => return Ok(value),
+                Err((error, false)) => return Err(error),
+                Err((error, true)) => error,
+            };
+
+            if attempt >= self.retries {
+                return Err(error);
+            }
+            let delay = delay_for_attempt(self.backoff, attempt).min(MAX_DELAY);
+            if self.debug {
+                eprintln!("error sending message: {}, retrying in {:?}", error, delay);
+            }
+            std::thread::sleep(delay);
+            attempt += 1;
+        }
+    }
+}
+
+fn delay_for_attempt(backoff: Duration, attempt: u32) -> Duration {
+    backoff.saturating_mul(2u32.saturating_pow(attempt))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A minimal HTTP server answering each request with the next of `responses`, formatted as
+    /// `status line\r\nheaders`. Returns the bodies of the requests it received.
+    fn server(responses: &[&str]) -> (String, std::thread::JoinHandle<Vec<String>>) {
+        use std::io::{BufRead, Read, Write};
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let responses = responses
+            .iter()
+            .map(|response| response.to_string())
+            .collect::<Vec<_>>();
+        let handle = std::thread::spawn(move || {
+            let mut bodies = vec![];
+            for (response, stream) in responses.iter().zip(listener.incoming()) {
+                let mut stream = stream.unwrap();
+                let mut reader = std::io::BufReader::new(stream.try_clone().unwrap());
+                let mut content_length = 0;
+                let mut line = String::new();
+                while reader.read_line(&mut line).unwrap() > 2 {
+                    if let Some(length) = line.to_lowercase().strip_prefix("content-length:") {
+                        content_length = length.trim().parse().unwrap();
+                    }
+                    line.clear();
+                }
+                let mut body = vec![0; content_length];
+                reader.read_exact(&mut body).unwrap();
+                bodies.push(String::from_utf8(body).unwrap());
+                write!(
+                    stream,
+                    "HTTP/1.1 {}\r\ncontent-length: 2\r\nconnection: close\r\n\r\nok",
+                    response
+                )
+                .unwrap();
+            }
+            bodies
+        });
+        (url, handle)
+    }
+
+    fn client() -> Client {
+        Client::new().backoff(Duration::from_millis(1))
+    }
+
+    #[test]
+    fn can_send_request() {
+        let (url, server) = server(&["200 OK"]);
+        assert_eq!(
+            client().post_json(&url, None, &serde_json::json!({"text": "hello"})),
+            Ok(String::from("ok"))
+        );
+        assert_eq!(server.join().unwrap(), vec![r#"{"text":"hello"}"#]);
+    }
+
+    #[test]
+    fn does_not_retry_client_errors() {
+        let (url, server) = server(&["404 Not Found"]);
+        // a retry would fail to connect once the server stopped, and report that error instead
+        assert_eq!(
+            client().send(reqwest::Method::POST, &url, &[], vec![]),
+            Err(String::from("ok (404 Not Found)"))
+        );
+        assert_eq!(server.join().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn retries_rate_limits_and_server_errors() {
+        let (url, server) = server(&["429 Too Many Requests", "503 Service Unavailable", "200 OK"]);
+        assert_eq!(
+            client().send(reqwest::Method::POST, &url, &[], b"hello".to_vec()),
+            Ok(String::from("ok"))
+        );
+        assert_eq!(server.join().unwrap(), vec!["hello", "hello", "hello"]);
+    }
+
+    #[test]
+    fn reports_last_error_when_out_of_retries() {
+        let (url, server) = server(&["500 Internal Server Error", "502 Bad Gateway"]);
+        assert_eq!(
+            client()
+                .retries(1)
+                .send(reqwest::Method::POST, &url, &[], vec![]),
+            Err(String::from("ok (502 Bad Gateway)"))
+        );
+        assert_eq!(server.join().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn waits_for_retry_after() {
+        let (url, server) = server(&["429 Too Many Requests\r\nretry-after: 1", "200 OK"]);
+        let start = std::time::Instant::now();
+        assert_eq!(
+            client().send(reqwest::Method::POST, &url, &[], vec![]),
+            Ok(String::from("ok"))
+        );
+        assert!(start.elapsed() >= Duration::from_secs(1));
+        assert_eq!(server.join().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn backoff_is_exponential() {
+        let backoff = Duration::from_millis(500);
+        assert_eq!(delay_for_attempt(backoff, 0), Duration::from_millis(500));
+        assert_eq!(delay_for_attempt(backoff, 1), Duration::from_secs(1));
+        assert_eq!(delay_for_attempt(backoff, 3), Duration::from_secs(4));
+    }
+}
@@ -0,0 +1,52 @@
+/// Replaces `${VARIABLE}` in `text` with the value returned by `lookup`. Variables that
+/// `lookup` doesn't know are left as is, and `$$` can be used for a literal `$`.
+pub(crate) fn interpolate<F>(text: &str, lookup: F) -> String
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(index) = rest.find('$') {
+        result.push_str(&rest[..index]);
+        rest = &rest[index..];
+        if rest.starts_with("$$") {
+            result.push('$');
+            rest = &rest[2..];
+        } else if let Some(variable) = rest
+            .strip_prefix("${")
+            .and_then(|after| after.find('}').map(|end| &after[..end]))
+        {
+            match lookup(variable) {
+                Some(value) => result.push_str(&value),
+                None => result.push_str(&rest[..variable.len() + 3]),
+            }
+            rest = &rest[variable.len() + 3..];
+        } else {
+            result.push('$');
+            rest = &rest[1..];
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn can_interpolate_variables() {
+        let lookup = |variable: &str| match variable {
+            "BUILD_NAME" => Some(String::from("42")),
+            _ => None,
+        };
+        assert_eq!(interpolate("build #${BUILD_NAME}", lookup), "build #42");
+        assert_eq!(interpolate("${UNKNOWN} stays", lookup), "${UNKNOWN} stays");
+        assert_eq!(interpolate("costs $$5 or $6", lookup), "costs $5 or $6");
+        assert_eq!(interpolate("$${BUILD_NAME}", lookup), "${BUILD_NAME}");
+        assert_eq!(
+            interpolate("unclosed ${BUILD_NAME", lookup),
+            "unclosed ${BUILD_NAME"
+        );
+    }
+}
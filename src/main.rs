@@ -5,18 +5,33 @@ use concourse_resource::*;
 mod message;
 use message::Message;
 mod concourse;
+mod git;
+mod http;
+mod interpolation;
+mod mention;
+mod route;
+mod slack;
+mod template;
 
 struct SlackNotifier {}
 
 #[derive(Serialize, Deserialize, Debug)]
 struct Version {
     status: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ts: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    channel_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    destination: Option<String>,
 }
 
 #[derive(Deserialize, Debug)]
 struct Source {
-    url: String,
-    channel: Option<String>,
+    #[serde(flatten)]
+    destination: Destination,
+    #[serde(default)]
+    destinations: Vec<Destination>,
     concourse_url: Option<String>,
     #[serde(flatten)]
     credentials: Option<ConcourseCredentials>,
@@ -24,6 +39,116 @@ struct Source {
     ssl_configuration: Option<SslConfiguration>,
     disabled: Option<bool>,
     debug: Option<bool>,
+    retries: Option<u32>,
+    retry_backoff: Option<u64>,
+    fail_on_error: Option<bool>,
+}
+
+impl Source {
+    fn destinations(&self) -> Vec<&Destination> {
+        if self.destinations.is_empty() {
+            vec![&self.destination]
+        } else {
+            self.destinations.iter().collect()
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct Destination {
+    #[serde(default)]
+    backend: Backend,
+    url: Option<String>,
+    token: Option<String>,
+    channel: Option<String>,
+    sender_name: Option<String>,
+    thread_key: Option<String>,
+    method: Option<String>,
+    #[serde(default)]
+    headers: std::collections::HashMap<String, String>,
+    body_template: Option<String>,
+    smtp: Option<SmtpConfiguration>,
+    alert_types: Option<Vec<AlertType>>,
+    #[serde(default)]
+    routes: Vec<route::Route>,
+    #[serde(default)]
+    mentions: Vec<mention::Mention>,
+    #[serde(default)]
+    mention_committer: bool,
+    #[serde(default)]
+    users: std::collections::HashMap<String, String>,
+}
+
+impl Destination {
+    fn accepts(&self, alert_type: &AlertType) -> bool {
+        self.alert_types
+            .as_ref()
+            .map(|alert_types| alert_types.contains(alert_type))
+            .unwrap_or(true)
+    }
+
+    /// The channel set in `params`, or of the first route matching the build, or of the destination.
+    fn channel_for(&self, params: &OutParams, build_metadata: &BuildMetadata) -> Option<String> {
+        params.channel.clone().or_else(|| {
+            self.routes
+                .iter()
+                .find(|route| route.matches(&params.alert_type, build_metadata))
+                .map(|route| route.channel.clone())
+                .or_else(|| self.channel.clone())
+        })
+    }
+
+    fn name(&self, channel: Option<&String>) -> String {
+        match channel {
+            Some(channel) => format!("{} {}", self.backend.name(), channel),
+            None => String::from(self.backend.name()),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum Backend {
+    Slack,
+    Teams,
+    Mattermost,
+    RocketChat,
+    Discord,
+    GoogleChat,
+    Webhook,
+    Email,
+}
+
+impl Backend {
+    fn name(&self) -> &'static str {
+        match self {
+            Backend::Slack => "slack",
+            Backend::Teams => "teams",
+            Backend::Mattermost => "mattermost",
+            Backend::RocketChat => "rocket_chat",
+            Backend::Discord => "discord",
+            Backend::GoogleChat => "google_chat",
+            Backend::Webhook => "webhook",
+            Backend::Email => "email",
+        }
+    }
+}
+
+impl Default for Backend {
+    fn default() -> Self {
+        Backend::Slack
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct SmtpConfiguration {
+    host: String,
+    port: Option<u16>,
+    starttls: Option<bool>,
+    username: Option<String>,
+    password: Option<String>,
+    from: String,
+    to: Vec<String>,
 }
 
 #[derive(Deserialize, Debug, Clone)]
@@ -44,7 +169,7 @@ struct ConcourseCredentials {
     password: String,
 }
 
-#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 #[serde(rename_all = "snake_case")]
 enum AlertType {
     Success,
@@ -54,6 +179,7 @@ enum AlertType {
     Errored,
     Fixed,
     Broke,
+    StillFailing,
     Custom,
 }
 
@@ -67,6 +193,7 @@ impl AlertType {
             AlertType::Errored => "Errored",
             AlertType::Fixed => "Fixed",
             AlertType::Broke => "Broke",
+            AlertType::StillFailing => "Still Failing",
             AlertType::Custom => "Build Finished",
         }
     }
@@ -78,7 +205,7 @@ impl Default for AlertType {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 #[serde(rename_all = "snake_case")]
 enum Mode {
     Concise,
@@ -92,19 +219,46 @@ impl Default for Mode {
     }
 }
 
-#[derive(Deserialize, Debug, Default)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+#[serde(rename_all = "snake_case")]
+enum Format {
+    Attachments,
+    Blocks,
+}
+
+impl Default for Format {
+    fn default() -> Self {
+        Format::Attachments
+    }
+}
+
+#[derive(Deserialize, Debug, Default, Clone)]
 #[serde(default)]
 struct OutParams {
     alert_type: AlertType,
     color: Option<String>,
     mode: Mode,
+    format: Format,
     message: Option<String>,
     channel: Option<String>,
     message_file: Option<String>,
+    template: Option<String>,
+    template_file: Option<String>,
+    template_data: std::collections::HashMap<String, String>,
+    payload_file: Option<String>,
+    interpolate_payload: bool,
     #[serde(default)]
     fail_if_message_file_missing: bool,
     disabled: bool,
     message_as_code: bool,
+    update_previous: Option<String>,
+    thread_from: Option<String>,
+    thread_broadcast: bool,
+    fail_on_error: Option<bool>,
+    mentions: Option<Vec<mention::Mention>>,
+    git_input: Option<String>,
+    failure_log_lines: Option<usize>,
+    show_timing: bool,
 }
 
 #[derive(Serialize, Debug, IntoMetadataKV)]
@@ -116,6 +270,33 @@ struct OutMetadata {
     alert_type: Option<AlertType>,
     #[serde(skip_serializing_if = "Option::is_none")]
     error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ts: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    channel_id: Option<String>,
+    /// Position of the destination that sent the message identified by `ts` and `channel_id`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    destination: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    destinations: Option<String>,
+}
+
+/// Information about the build from the Concourse API, shared by all destinations.
+#[derive(Default)]
+struct BuildContext {
+    previous_build: Option<concourse::Build>,
+    current_build: Option<concourse::Build>,
+    failed_step: Option<concourse::FailedStep>,
+    streak: Option<concourse::Streak>,
+}
+
+/// Outcome of sending to a destination, `None` if it was skipped because of its alert types.
+struct DestinationResult {
+    /// Position of the destination in the destinations of the source
+    index: usize,
+    name: String,
+    channel: Option<String>,
+    result: Option<Result<message::Sent, String>>,
 }
 
 impl std::fmt::Display for OutMetadata {
@@ -150,15 +331,94 @@ impl std::fmt::Display for OutMetadata {
     }
 }
 
-fn try_to_send(url: &str, message: &slack_push::Message) -> Result<(), String> {
-    reqwest::blocking::Client::new()
-        .post(reqwest::Url::parse(url).map_err(|err| format!("{}", err))?)
-        .json(message)
-        .send()
-        .map_err(|err| format!("{}", err))?
-        .text()
-        .map_err(|err| format!("{}", err))?;
-    Ok(())
+fn http_client(source: &Source) -> http::Client {
+    let mut client = http::Client::new().debug(source.debug.unwrap_or(false));
+    if let Some(retries) = source.retries {
+        client = client.retries(retries);
+    }
+    if let Some(retry_backoff) = source.retry_backoff {
+        client = client.backoff(std::time::Duration::from_millis(retry_backoff));
+    }
+    client
+}
+
+fn try_to_send(
+    source: &Source,
+    destination: &Destination,
+    message: &message::Payload,
+    previous: Option<&slack::MessageRef>,
+) -> Result<message::Sent, String> {
+    let client = http_client(source);
+
+    let url = || {
+        destination
+            .url
+            .as_ref()
+            .ok_or_else(|| String::from("missing url in resource configuration"))
+    };
+
+    match (&destination.backend, previous, message) {
+        (Backend::Slack, _, message::Payload::Json(message)) => {
+            try_to_send_to_slack(&client, destination, message, previous)
+        }
+        (backend, Some(_), _) if *backend != Backend::Slack => Err(format!(
+            "updating a previous message is not supported with backend {:?}",
+            backend
+        )),
+        (Backend::Teams, None, message::Payload::Json(message)) => {
+            url().and_then(|url| message::teams::send(&client, url, message))
+        }
+        (Backend::Mattermost, None, message::Payload::Json(message)) => {
+            url().and_then(|url| message::send_to_webhook(&client, url, message))
+        }
+        (Backend::RocketChat, None, message::Payload::Json(message)) => {
+            url().and_then(|url| message::rocketchat::send(&client, url, message))
+        }
+        (Backend::Discord, None, message::Payload::Json(message)) => {
+            url().and_then(|url| message::send_to_webhook(&client, url, message))
+        }
+        (Backend::GoogleChat, None, message::Payload::Json(message)) => {
+            url().and_then(|url| message::google_chat::send(&client, url, message))
+        }
+        (Backend::Webhook, None, message::Payload::Json(message)) => url().and_then(|url| {
+            message::webhook::send(
+                &client,
+                url,
+                destination.method.as_ref(),
+                &destination.headers,
+                message,
+            )
+        }),
+        (Backend::Email, None, message::Payload::Email(email)) => destination
+            .smtp
+            .as_ref()
+            .ok_or_else(|| String::from("missing smtp in resource configuration"))
+            .and_then(|smtp| message::email::send(&client, smtp, email)),
+        (backend, _, _) => Err(format!("invalid message for backend {}", backend.name())),
+    }
+}
+
+fn try_to_send_to_slack<T: Serialize>(
+    client: &http::Client,
+    destination: &Destination,
+    message: &T,
+    previous: Option<&slack::MessageRef>,
+) -> Result<message::Sent, String> {
+    match (
+        destination.token.as_ref(),
+        destination.url.as_ref(),
+        previous,
+    ) {
+        (Some(token), _, Some(previous)) => slack::update_message(client, token, previous, message),
+        (Some(token), _, None) => slack::post_message(client, token, message),
+        (None, _, Some(_)) => Err(String::from(
+            "updating a previous message requires a token in resource configuration",
+        )),
+        (None, Some(url), None) => message::send_to_webhook(client, url, message),
+        (None, None, None) => Err(String::from(
+            "missing url or token in resource configuration",
+        )),
+    }
 }
 
 impl Resource for SlackNotifier {
@@ -179,14 +439,24 @@ impl Resource for SlackNotifier {
 
     fn resource_in(
         _source: Option<Self::Source>,
-        _version: Self::Version,
+        version: Self::Version,
         _params: Option<Self::InParams>,
-        _output_path: &str,
+        output_path: &str,
     ) -> Result<InOutput<Self::Version, Self::InMetadata>, Box<dyn std::error::Error>> {
+        if let (Some(channel_id), Some(ts)) = (version.channel_id.as_ref(), version.ts.as_ref()) {
+            slack::MessageRef {
+                channel_id: channel_id.clone(),
+                ts: ts.clone(),
+                destination: version
+                    .destination
+                    .as_ref()
+                    .and_then(|destination| destination.parse().ok())
+                    .unwrap_or(0),
+            }
+            .write_to(std::path::Path::new(output_path))?;
+        }
         Ok(InOutput {
-            version: Self::Version {
-                status: String::from("static"),
-            },
+            version,
             metadata: None,
         })
     }
@@ -197,57 +467,116 @@ impl Resource for SlackNotifier {
         input_path: &str,
     ) -> OutOutput<Self::Version, Self::OutMetadata> {
         let metadata = if let Some(source) = source {
-            let mut params = params.unwrap_or_default();
+            let params = params.unwrap_or_default();
 
-            if params.channel.is_none() && source.channel.is_some() {
-                params.channel = source.channel.clone();
-            }
-
-            if source.debug.unwrap_or(false) {
-                eprintln!("sending a message to {:?}", params.channel);
-            }
+            let context = Self::build_context(&source, &params);
 
-            if !Self::should_send_message(&source, &params) {
+            if !Self::should_send_message(&source, &params, &context) {
                 if source.debug.unwrap_or(false) {
                     eprintln!("not sending message");
                 }
 
                 OutMetadata {
+                    channel: source
+                        .destination
+                        .channel_for(&params, &Self::build_metadata()),
                     alert_type: Some(params.alert_type),
-                    channel: params.channel,
                     sent: false,
                     error: None,
+                    ts: None,
+                    channel_id: None,
+                    destination: None,
+                    destinations: None,
                 }
             } else {
-                let message = Message::new(&params, input_path)
-                    .into_slack_message(Self::build_metadata(), &params);
+                let previous = params
+                    .update_previous
+                    .as_ref()
+                    .map(|previous| {
+                        slack::MessageRef::read_from(
+                            &std::path::Path::new(input_path).join(previous),
+                        )
+                    })
+                    .transpose();
+
+                let thread = message::thread_from(&params, input_path);
+
+                let commit = params.git_input.as_ref().map(|git_input| {
+                    git::Commit::read_from(&std::path::Path::new(input_path).join(git_input))
+                });
 
                 if source.debug.unwrap_or(false) {
-                    eprintln!("trying to send message {:?}", message);
+                    eprintln!("previous message: {:?}", previous);
+                    eprintln!("thread: {:?}", thread);
+                    eprintln!("commit: {:?}", commit);
                 }
 
-                if let Result::Err(error) = try_to_send(&source.url, &message) {
-                    if source.debug.unwrap_or(false) {
-                        eprintln!("error sending message: {:?}", error);
-                    }
-
-                    OutMetadata {
-                        alert_type: Some(params.alert_type),
-                        channel: params.channel,
-                        sent: false,
-                        error: Some(error),
-                    }
-                } else {
-                    if source.debug.unwrap_or(false) {
-                        eprintln!("message sent");
-                    }
-                    OutMetadata {
-                        alert_type: Some(params.alert_type),
-                        channel: params.channel,
-                        sent: true,
-                        error: None,
-                    }
-                }
+                let build_metadata = Self::build_metadata();
+                let results = source
+                    .destinations()
+                    .into_iter()
+                    .enumerate()
+                    .map(|(index, destination)| {
+                        let mut params = params.clone();
+                        params.channel = destination.channel_for(&params, &build_metadata);
+                        let name = destination.name(params.channel.as_ref());
+
+                        if !destination.accepts(&params.alert_type) {
+                            if source.debug.unwrap_or(false) {
+                                eprintln!("not sending message to {}", name);
+                            }
+                            return DestinationResult {
+                                index,
+                                name,
+                                channel: params.channel,
+                                result: None,
+                            };
+                        }
+
+                        if source.debug.unwrap_or(false) {
+                            eprintln!("sending a message to {}", name);
+                        }
+
+                        let message = Self::build_message(
+                            &source,
+                            destination,
+                            &params,
+                            &context,
+                            Self::message_ref_for(thread.as_ref(), index, destination),
+                            commit.as_ref(),
+                            input_path,
+                        );
+
+                        if source.debug.unwrap_or(false) {
+                            eprintln!("trying to send message {:?}", message);
+                        }
+
+                        let previous = previous.clone().map(|previous| {
+                            Self::message_ref_for(previous.as_ref(), index, destination)
+                        });
+                        let result = message.and_then(|message| {
+                            previous.and_then(|previous| {
+                                try_to_send(&source, destination, &message, previous.as_ref())
+                            })
+                        });
+
+                        if source.debug.unwrap_or(false) {
+                            match result.as_ref() {
+                                Ok(sent) => eprintln!("message sent: {:?}", sent),
+                                Err(error) => eprintln!("error sending message: {:?}", error),
+                            }
+                        }
+
+                        DestinationResult {
+                            index,
+                            name,
+                            channel: params.channel,
+                            result: Some(result),
+                        }
+                    })
+                    .collect::<Vec<_>>();
+
+                Self::out_metadata(params.alert_type, results, !source.destinations.is_empty())
             }
         } else {
             OutMetadata {
@@ -255,33 +584,131 @@ impl Resource for SlackNotifier {
                 channel: None,
                 sent: false,
                 error: Some(String::from("missing resource configuration")),
+                ts: None,
+                channel_id: None,
+                destination: None,
+                destinations: None,
             }
         };
 
         OutOutput {
             version: Self::Version {
                 status: format!("{}", metadata),
+                ts: metadata.ts.clone(),
+                channel_id: metadata.channel_id.clone(),
+                destination: metadata.destination.clone(),
             },
             metadata: Some(metadata),
         }
     }
 }
 
-fn previous_build_name(build_name: &String) -> String {
-    format!(
-        "{}",
-        build_name
-            .parse::<f32>()
-            .map(|bn| bn.trunc() as u32)
-            .unwrap_or(1)
-            - 1
-    )
-}
-
 impl SlackNotifier {
+    /// `fail_on_error` from the params, or from the source if not set.
+    fn fail_on_error(source: Option<&Source>, params: Option<&OutParams>) -> bool {
+        params
+            .and_then(|params| params.fail_on_error)
+            .or_else(|| source.and_then(|source| source.fail_on_error))
+            .unwrap_or(false)
+    }
+
+    /// The error that should fail the step, if any.
+    fn step_error(fail_on_error: bool, metadata: &OutMetadata) -> Option<&String> {
+        if fail_on_error {
+            metadata.error.as_ref()
+        } else {
+            None
+        }
+    }
+
+    /// Merge the results of sending to each destination. Destinations that were skipped because of
+    /// their alert types have no result.
+    fn out_metadata(
+        alert_type: AlertType,
+        results: Vec<DestinationResult>,
+        with_destinations: bool,
+    ) -> OutMetadata {
+        let summary = results
+            .iter()
+            .map(|destination| match destination.result.as_ref() {
+                None => format!("{}: not sent", destination.name),
+                Some(Ok(message::Sent {
+                    ts: Some(ts),
+                    channel_id: Some(channel_id),
+                })) => format!("{}: sent to {} at {}", destination.name, channel_id, ts),
+                Some(Ok(_)) => format!("{}: sent", destination.name),
+                Some(Err(error)) => format!("{}: {}", destination.name, error),
+            })
+            .collect::<Vec<_>>()
+            .join("; ");
+        let errors = results
+            .iter()
+            .filter_map(|destination| match destination.result.as_ref() {
+                Some(Err(error)) if with_destinations => {
+                    Some(format!("{}: {}", destination.name, error))
+                }
+                Some(Err(error)) => Some(error.clone()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        let channel = if with_destinations {
+            None
+        } else {
+            results
+                .first()
+                .and_then(|destination| destination.channel.clone())
+        };
+        let mut metadata = OutMetadata {
+            alert_type: Some(alert_type),
+            channel,
+            sent: false,
+            error: if errors.is_empty() {
+                None
+            } else {
+                Some(errors.join("; "))
+            },
+            ts: None,
+            channel_id: None,
+            destination: None,
+            destinations: if with_destinations {
+                Some(summary)
+            } else {
+                None
+            },
+        };
+        for destination in results {
+            if let Some(Ok(sent)) = destination.result {
+                metadata.sent = true;
+                // the version can only identify one message, `update_previous` and `thread_from`
+                // use it with the destination that sent it
+                if metadata.ts.is_none() && sent.ts.is_some() {
+                    metadata.ts = sent.ts;
+                    metadata.channel_id = sent.channel_id;
+                    metadata.destination = Some(destination.index.to_string());
+                }
+            }
+        }
+        metadata
+    }
+
+    /// The message from a previous step, if it was sent by this destination. Other destinations
+    /// send a new message.
+    fn message_ref_for(
+        message_ref: Option<&slack::MessageRef>,
+        index: usize,
+        destination: &Destination,
+    ) -> Option<slack::MessageRef> {
+        message_ref
+            .filter(|message_ref| {
+                destination.backend == Backend::Slack && message_ref.destination == index
+            })
+            .cloned()
+    }
+
     fn should_send_message(
         source: &<Self as Resource>::Source,
         params: &<Self as Resource>::OutParams,
+        context: &BuildContext,
     ) -> bool {
         if source.disabled.unwrap_or(false) || params.disabled {
             if source.debug.unwrap_or(false) {
@@ -289,47 +716,310 @@ impl SlackNotifier {
             }
             return false;
         }
-        if params.alert_type == AlertType::Broke || params.alert_type == AlertType::Fixed {
-            if source.debug.unwrap_or(false) {
-                eprintln!("checking status of last build");
-            }
-            let metadata = Self::build_metadata();
-            let mut concourse = concourse::Concourse::new(
-                source
-                    .concourse_url
+        if params.alert_type == AlertType::Broke
+            || params.alert_type == AlertType::Fixed
+            || params.alert_type == AlertType::StillFailing
+        {
+            match (
+                &params.alert_type,
+                context
+                    .previous_build
                     .as_ref()
-                    .map(String::as_ref)
-                    .unwrap_or(&metadata.atc_external_url),
-            );
-
-            if let Some(ssl_configuration) = source.ssl_configuration.as_ref() {
-                concourse = concourse.ssl_configuration(ssl_configuration.clone());
+                    .and_then(|build| build.status.as_ref()),
+            ) {
+                (AlertType::Broke, Some(concourse::Status::Succeeded)) => true,
+                (AlertType::Fixed, Some(concourse::Status::Succeeded)) => false,
+                (AlertType::Fixed, Some(_)) => true,
+                (AlertType::StillFailing, Some(concourse::Status::Failed))
+                | (AlertType::StillFailing, Some(concourse::Status::Errored)) => true,
+                (_, _) => false,
             }
+        } else {
+            true
+        }
+    }
+
+    fn build_message(
+        source: &<Self as Resource>::Source,
+        destination: &Destination,
+        params: &<Self as Resource>::OutParams,
+        context: &BuildContext,
+        thread: Option<slack::MessageRef>,
+        commit: Option<&git::Commit>,
+        input_path: &str,
+    ) -> Result<message::Payload, String> {
+        let build_metadata = Self::build_metadata();
+        if let Some(payload_file) = params.payload_file.as_ref() {
+            let payload = message::payload_from_file(
+                payload_file,
+                params,
+                input_path,
+                &build_metadata,
+                thread.as_ref(),
+            )?;
+            return match destination.backend {
+                Backend::Email => serde_json::from_value(payload)
+                    .map(message::Payload::Email)
+                    .map_err(|err| format!("invalid email in file {}: {}", payload_file, err)),
+                _ => Ok(message::Payload::Json(payload)),
+            };
+        }
 
-            concourse = concourse.build();
+        let mut message = Message::new(params, input_path, &build_metadata, context);
+        message.thread = thread;
+        if params.show_timing {
+            message.build = context.current_build.clone();
+        }
+        message.streak = context.streak.clone();
+        if destination.backend == Backend::Slack {
+            message.failed_step = context.failed_step.clone();
+        }
+        message.commit = commit.cloned();
+        message.mentions = mention::mentions_for(
+            params.mentions.as_ref().unwrap_or(&destination.mentions),
+            &params.alert_type,
+        );
+        if destination.mention_committer
+            && matches!(
+                params.alert_type,
+                AlertType::Broke | AlertType::Failed | AlertType::StillFailing
+            )
+        {
+            if let Some(committer) = Self::committer(source, destination, commit) {
+                message.mentions = Some(match message.mentions {
+                    Some(mentions) => format!("{} <@{}>", mentions, committer),
+                    None => format!("<@{}>", committer),
+                });
+            }
+        }
+        match destination.backend {
+            Backend::Slack => {
+                serde_json::to_value(message.into_slack_message(build_metadata, params))
+            }
+            Backend::Teams => {
+                serde_json::to_value(message.into_teams_message(build_metadata, params))
+            }
+            Backend::Mattermost => serde_json::to_value(message.into_mattermost_message(
+                build_metadata,
+                params,
+                destination.sender_name.as_ref(),
+            )),
+            Backend::RocketChat => serde_json::to_value(message.into_rocketchat_message(
+                build_metadata,
+                params,
+                destination.sender_name.as_ref(),
+            )),
+            Backend::Discord => serde_json::to_value(message.into_discord_message(
+                build_metadata,
+                params,
+                destination.sender_name.as_ref(),
+            )),
+            Backend::GoogleChat => serde_json::to_value(message.into_google_chat_message(
+                build_metadata,
+                params,
+                destination.thread_key.as_ref(),
+            )),
+            Backend::Email => {
+                return Ok(message::Payload::Email(
+                    message.into_email(build_metadata, params),
+                ))
+            }
+            Backend::Webhook => {
+                return message
+                    .into_webhook_body(build_metadata, params, destination.body_template.as_ref())
+                    .map(message::Payload::Json)
+            }
+        }
+        .map(message::Payload::Json)
+        .map_err(|err| format!("{}", err))
+    }
 
-            if let Some(credentials) = &source.credentials {
-                concourse = concourse.auth(&credentials.username, &credentials.password);
+    /// Slack user ID of the author of the commit in `git_input`, from the `users` map or looked up
+    /// by email with the token.
+    fn committer(
+        source: &<Self as Resource>::Source,
+        destination: &Destination,
+        commit: Option<&git::Commit>,
+    ) -> Option<String> {
+        let email = commit?.author_email.as_ref()?;
+        if source.debug.unwrap_or(false) {
+            eprintln!("commit author: {}", email);
+        }
+        if let Some(user) = destination.users.get(email) {
+            return Some(user.clone());
+        }
+        let token = destination.token.as_ref()?;
+        slack::lookup_user_by_email(&http_client(source), token, email)
+            .map_err(|err| {
                 if source.debug.unwrap_or(false) {
-                    eprintln!("authenticated to concourse: {}", concourse.is_authed());
+                    eprintln!("error looking up Slack user for {}: {}", email, err);
                 }
-            }
+            })
+            .ok()
+    }
 
+    /// Concourse client for the ATC running this build, authenticated if credentials are set.
+    fn concourse(
+        source: &<Self as Resource>::Source,
+        metadata: &BuildMetadata,
+    ) -> concourse::Concourse {
+        let mut concourse = concourse::Concourse::new(
+            source
+                .concourse_url
+                .as_ref()
+                .map(String::as_ref)
+                .unwrap_or(&metadata.atc_external_url),
+        );
+
+        if let Some(ssl_configuration) = source.ssl_configuration.as_ref() {
+            concourse = concourse.ssl_configuration(ssl_configuration.clone());
+        }
+
+        concourse = concourse.build();
+
+        if let Some(credentials) = &source.credentials {
+            concourse = concourse.auth(&credentials.username, &credentials.password);
             if source.debug.unwrap_or(false) {
-                eprintln!(
-                    "getting build {:?}/{:?}/{:?} #{:?}",
-                    &metadata.team_name,
-                    metadata
-                        .pipeline_name
-                        .as_ref()
-                        .map(String::as_ref)
-                        .unwrap_or(""),
-                    metadata.job_name.as_ref().map(String::as_ref).unwrap_or(""),
-                    metadata.name.as_ref().map(previous_build_name)
-                );
+                eprintln!("authenticated to concourse: {}", concourse.is_authed());
             }
+        }
 
-            let last_build = concourse.get_build(
+        concourse
+    }
+
+    /// Fetch what the notification needs from the Concourse API, once for all destinations.
+    fn build_context(
+        source: &<Self as Resource>::Source,
+        params: &<Self as Resource>::OutParams,
+    ) -> BuildContext {
+        if source.disabled.unwrap_or(false) || params.disabled {
+            return BuildContext::default();
+        }
+        let renders_message = params.payload_file.is_none();
+        let uses_template = params.template.is_some() || params.template_file.is_some();
+        let failing = matches!(
+            params.alert_type,
+            AlertType::Failed | AlertType::Errored | AlertType::Broke | AlertType::StillFailing
+        );
+        let log_lines = params.failure_log_lines.unwrap_or(0);
+
+        let accepted_by_slack = source.destinations().iter().any(|destination| {
+            destination.backend == Backend::Slack && destination.accepts(&params.alert_type)
+        });
+
+        let needs_previous_build = matches!(
+            params.alert_type,
+            AlertType::Broke | AlertType::Fixed | AlertType::StillFailing
+        ) || (renders_message && uses_template);
+        let needs_current_build = renders_message
+            && (uses_template
+                || (params.show_timing
+                    && params.mode == Mode::NormalWithInfo
+                    && accepted_by_slack));
+        let needs_failed_step = renders_message
+            && failing
+            && log_lines > 0
+            && params.mode != Mode::Concise
+            && accepted_by_slack;
+        let needs_streak = renders_message
+            && (params.alert_type == AlertType::StillFailing || (failing && uses_template));
+        if !(needs_previous_build || needs_current_build || needs_failed_step || needs_streak) {
+            return BuildContext::default();
+        }
+
+        let metadata = Self::build_metadata();
+        let concourse = Self::concourse(source, &metadata);
+        let streak = if needs_streak {
+            Self::failing_streak(source, &concourse, &metadata)
+        } else {
+            None
+        };
+        BuildContext {
+            previous_build: match streak.as_ref() {
+                // the streak was counted from the previous build
+                Some((_, previous_build)) => previous_build.clone(),
+                None if needs_previous_build => Self::previous_build(source, &concourse, &metadata),
+                None => None,
+            },
+            current_build: if needs_current_build {
+                Self::current_build(source, &concourse, &metadata)
+            } else {
+                None
+            },
+            failed_step: if needs_failed_step {
+                Self::failed_step(source, &concourse, &metadata, log_lines)
+            } else {
+                None
+            },
+            streak: streak.map(|(streak, _)| streak),
+        }
+    }
+
+    fn current_build(
+        source: &<Self as Resource>::Source,
+        concourse: &concourse::Concourse,
+        metadata: &BuildMetadata,
+    ) -> Option<concourse::Build> {
+        let build = concourse.get_build(
+            metadata.id.parse::<u32>().ok()?,
+            source.debug.unwrap_or(false),
+        );
+
+        if source.debug.unwrap_or(false) {
+            eprintln!("current build: {:?}", build);
+        }
+
+        build
+    }
+
+    fn failed_step(
+        source: &<Self as Resource>::Source,
+        concourse: &concourse::Concourse,
+        metadata: &BuildMetadata,
+        log_lines: usize,
+    ) -> Option<concourse::FailedStep> {
+        let failed_step = concourse.failed_step(
+            metadata.id.parse::<u32>().ok()?,
+            log_lines,
+            source.debug.unwrap_or(false),
+        );
+
+        if source.debug.unwrap_or(false) {
+            eprintln!("failed step: {:?}", failed_step);
+        }
+
+        failed_step
+    }
+
+    fn failing_streak(
+        source: &<Self as Resource>::Source,
+        concourse: &concourse::Concourse,
+        metadata: &BuildMetadata,
+    ) -> Option<(concourse::Streak, Option<concourse::Build>)> {
+        let streak = concourse.failing_streak(
+            &metadata.team_name,
+            metadata.pipeline_name.as_deref()?,
+            metadata.job_name.as_deref()?,
+            metadata.id.parse::<u32>().ok()?,
+            metadata.name.as_deref()?,
+            source.debug.unwrap_or(false),
+        );
+
+        if source.debug.unwrap_or(false) {
+            eprintln!("failing streak: {:?}", streak);
+        }
+
+        streak
+    }
+
+    fn previous_build(
+        source: &<Self as Resource>::Source,
+        concourse: &concourse::Concourse,
+        metadata: &BuildMetadata,
+    ) -> Option<concourse::Build> {
+        if source.debug.unwrap_or(false) {
+            eprintln!(
+                "getting build before {:?}/{:?}/{:?} #{:?}",
                 &metadata.team_name,
                 metadata
                     .pipeline_name
@@ -337,32 +1027,120 @@ impl SlackNotifier {
                     .map(String::as_ref)
                     .unwrap_or(""),
                 metadata.job_name.as_ref().map(String::as_ref).unwrap_or(""),
-                metadata
-                    .name
-                    .as_ref()
-                    .map(previous_build_name)
-                    .and_then(|bn| bn.parse::<u32>().ok())
-                    .unwrap_or(1),
-                source.debug.unwrap_or(false),
+                metadata.name
             );
+        }
 
-            if source.debug.unwrap_or(false) {
-                eprintln!("last build: {:?}", last_build);
-            }
+        let last_build = concourse.previous_build(
+            &metadata.team_name,
+            metadata
+                .pipeline_name
+                .as_ref()
+                .map(String::as_ref)
+                .unwrap_or(""),
+            metadata.job_name.as_ref().map(String::as_ref).unwrap_or(""),
+            metadata.id.parse::<u32>().ok()?,
+            source.debug.unwrap_or(false),
+        );
 
-            match (&params.alert_type, last_build.and_then(|b| b.status)) {
-                (AlertType::Broke, Some(concourse::Status::Succeeded)) => true,
-                (AlertType::Fixed, Some(concourse::Status::Succeeded)) => false,
-                (AlertType::Fixed, Some(_)) => true,
-                (_, _) => false,
+        if source.debug.unwrap_or(false) {
+            eprintln!("last build: {:?}", last_build);
+        }
+
+        last_build
+    }
+}
+
+/// Input of the `check`, `in` and `out` scripts, read from stdin.
+#[derive(Deserialize, Debug)]
+struct Input<P> {
+    source: Option<Source>,
+    version: Option<Version>,
+    params: Option<P>,
+}
+
+/// Run the script `command` of the resource with its JSON `input` and the directory given as
+/// argument, returning the JSON to write to stdout or the error failing the step.
+///
+/// This replaces `create_resource!`, as `out` must fail the step when `fail_on_error` is set.
+fn run(command: &str, input: &str, path: &str) -> Result<serde_json::Value, String> {
+    let invalid_input = |err: serde_json::Error| format!("invalid input: {}", err);
+    let output = |version: Version, metadata: Vec<KV>| {
+        serde_json::json!({
+            "version": version,
+            "metadata": metadata
+                .into_iter()
+                .map(|kv| serde_json::json!({ "name": kv.name, "value": kv.value }))
+                .collect::<Vec<_>>(),
+        })
+    };
+
+    match command.rsplit('/').next() {
+        Some("check") => {
+            let input =
+                serde_json::from_str::<Input<serde_json::Value>>(input).map_err(invalid_input)?;
+            serde_json::to_value(SlackNotifier::resource_check(input.source, input.version))
+                .map_err(|err| format!("{}", err))
+        }
+        Some("in") => {
+            let input =
+                serde_json::from_str::<Input<serde_json::Value>>(input).map_err(invalid_input)?;
+            let version = input
+                .version
+                .ok_or_else(|| String::from("invalid input: missing version"))?;
+            // `in` has no params
+            let result = SlackNotifier::resource_in(input.source, version, None, path)
+                .map_err(|err| format!("{}", err))?;
+            Ok(output(
+                result.version,
+                result
+                    .metadata
+                    .map(IntoMetadataKV::into_metadata_kv)
+                    .unwrap_or_default(),
+            ))
+        }
+        Some("out") => {
+            let input = serde_json::from_str::<Input<OutParams>>(input).map_err(invalid_input)?;
+            let fail_on_error =
+                SlackNotifier::fail_on_error(input.source.as_ref(), input.params.as_ref());
+            let result = SlackNotifier::resource_out(input.source, input.params, path);
+            if let Some(error) = result
+                .metadata
+                .as_ref()
+                .and_then(|metadata| SlackNotifier::step_error(fail_on_error, metadata))
+            {
+                return Err(format!("error sending notification: {}", error));
             }
-        } else {
-            true
+            Ok(output(
+                result.version,
+                result
+                    .metadata
+                    .map(IntoMetadataKV::into_metadata_kv)
+                    .unwrap_or_default(),
+            ))
         }
+        _ => Err(format!("unexpected command {}", command)),
     }
 }
 
-create_resource!(SlackNotifier);
+fn main() {
+    let mut args = std::env::args();
+    let command = args.next().unwrap_or_default();
+    let path = args.next().unwrap_or_default();
+    let mut input = String::new();
+    if let Err(err) = std::io::Read::read_to_string(&mut std::io::stdin(), &mut input) {
+        eprintln!("error reading input: {}", err);
+        std::process::exit(1);
+    }
+
+    match run(&command, &input, &path) {
+        Ok(output) => println!("{}", output),
+        Err(error) => {
+            eprintln!("{}", error);
+            std::process::exit(1);
+        }
+    }
+}
 
 #[cfg(test)]
 mod tests {
@@ -377,8 +1155,250 @@ mod tests {
     }
 
     #[test]
-    fn can_get_previous_build_number() {
-        assert_eq!(previous_build_name(&String::from("5")), "4");
-        assert_eq!(previous_build_name(&String::from("6.1")), "5");
+    fn can_deserialize_source_with_credentials() {
+        let source = r#"{"url": "https://example.com", "sender_name": "ci", "username": "user", "password": "pass"}"#;
+
+        let source = serde_json::from_str::<Source>(source).unwrap();
+        assert_eq!(source.destination.sender_name.as_deref(), Some("ci"));
+        assert_eq!(source.credentials.unwrap().username, "user");
+    }
+
+    #[test]
+    fn can_fail_step_on_error() {
+        let source = serde_json::from_str::<Source>(
+            r#"{"url": "https://example.com", "fail_on_error": true}"#,
+        )
+        .unwrap();
+        let params = serde_json::from_str::<OutParams>(r#"{"fail_on_error": false}"#).unwrap();
+        assert!(SlackNotifier::fail_on_error(Some(&source), None));
+        assert!(!SlackNotifier::fail_on_error(Some(&source), Some(&params)));
+        assert!(!SlackNotifier::fail_on_error(None, None));
+
+        let mut metadata = OutMetadata {
+            alert_type: None,
+            channel: None,
+            sent: false,
+            error: Some(String::from("channel_not_found")),
+            ts: None,
+            channel_id: None,
+            destination: None,
+            destinations: None,
+        };
+        assert_eq!(
+            SlackNotifier::step_error(true, &metadata).map(String::as_str),
+            Some("channel_not_found")
+        );
+        assert_eq!(SlackNotifier::step_error(false, &metadata), None);
+        metadata.error = None;
+        assert_eq!(SlackNotifier::step_error(true, &metadata), None);
+
+        let path = std::env::temp_dir().to_string_lossy().into_owned();
+        assert_eq!(
+            run(
+                "/opt/resource/out",
+                r#"{"params": {"fail_on_error": true}}"#,
+                &path
+            ),
+            Err(String::from(
+                "error sending notification: missing resource configuration"
+            ))
+        );
+        let output = run("/opt/resource/out", r#"{"params": {}}"#, &path).unwrap();
+        assert_eq!(output["version"]["status"], "error sending notification");
+        assert!(output["metadata"].is_array());
+    }
+
+    #[test]
+    fn does_not_fetch_current_build_by_default() {
+        let source = serde_json::from_str::<Source>(
+            r#"{"url": "https://example.com", "username": "user", "password": "pass"}"#,
+        )
+        .unwrap();
+        let params = serde_json::from_str::<OutParams>(r#"{"alert_type": "success"}"#).unwrap();
+        assert_eq!(params.mode, Mode::NormalWithInfo);
+        assert!(!params.show_timing);
+        assert!(SlackNotifier::build_context(&source, &params)
+            .current_build
+            .is_none());
+    }
+
+    #[test]
+    fn does_not_read_failed_step_by_default() {
+        let source = serde_json::from_str::<Source>(r#"{"url": "https://example.com"}"#).unwrap();
+        let params = serde_json::from_str::<OutParams>(r#"{"alert_type": "failed"}"#).unwrap();
+        assert!(SlackNotifier::build_context(&source, &params)
+            .failed_step
+            .is_none());
+    }
+
+    #[test]
+    fn can_deserialize_destinations() {
+        let source = r#"{"url": "https://example.com", "destinations": [{"url": "https://example.com/team", "channel": "team-ci"}, {"backend": "teams", "url": "https://example.com/releases", "alert_types": ["success"]}]}"#;
+
+        let source = serde_json::from_str::<Source>(source).unwrap();
+        let destinations = source.destinations();
+        assert_eq!(destinations.len(), 2);
+        assert!(destinations[0].accepts(&AlertType::Failed));
+        assert!(!destinations[1].accepts(&AlertType::Failed));
+        assert!(destinations[1].accepts(&AlertType::Success));
+    }
+
+    #[test]
+    fn can_route_to_first_matching_channel() {
+        let source = r##"{"url": "https://example.com", "channel": "#ci", "routes": [{"alert_types": ["failed"], "pipeline": "c*", "channel": "#ci-failures"}, {"job": "unit *", "channel": "#unit-tests"}, {"pipeline": "ci", "channel": "#never"}]}"##;
+        let source = serde_json::from_str::<Source>(source).unwrap();
+        let build_metadata = message::tests::build_metadata();
+        let channel = |params: serde_json::Value| {
+            source
+                .destination
+                .channel_for(&message::tests::out_params(params), &build_metadata)
+        };
+
+        assert_eq!(
+            channel(serde_json::json!({"alert_type": "failed"})).as_deref(),
+            Some("#ci-failures")
+        );
+        assert_eq!(
+            channel(serde_json::json!({"alert_type": "success"})).as_deref(),
+            Some("#unit-tests")
+        );
+        assert_eq!(
+            channel(serde_json::json!({"alert_type": "failed", "channel": "#explicit"})).as_deref(),
+            Some("#explicit")
+        );
+
+        let mut build_metadata = message::tests::build_metadata();
+        build_metadata.pipeline_name = Some(String::from("release"));
+        build_metadata.job_name = Some(String::from("deploy"));
+        assert_eq!(
+            source
+                .destination
+                .channel_for(&OutParams::default(), &build_metadata)
+                .as_deref(),
+            Some("#ci")
+        );
+    }
+
+    #[test]
+    fn can_find_committer_in_users() {
+        let source = r#"{"url": "https://example.com", "mention_committer": true, "users": {"jane@example.com": "U123"}}"#;
+        let source = serde_json::from_str::<Source>(source).unwrap();
+        let commit = |email: &str| git::Commit {
+            author_email: Some(String::from(email)),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            SlackNotifier::committer(
+                &source,
+                &source.destination,
+                Some(&commit("jane@example.com"))
+            )
+            .as_deref(),
+            Some("U123")
+        );
+        // without a token, unknown committers can't be looked up in Slack
+        assert_eq!(
+            SlackNotifier::committer(
+                &source,
+                &source.destination,
+                Some(&commit("john@example.com"))
+            ),
+            None
+        );
+        assert_eq!(
+            SlackNotifier::committer(&source, &source.destination, None),
+            None
+        );
+    }
+
+    #[test]
+    fn can_update_only_the_message_of_the_destination() {
+        let source = r##"{"destinations": [{"token": "xoxb-team", "channel": "#team-ci"}, {"token": "xoxb-releases", "channel": "#releases", "alert_types": ["success"]}, {"backend": "teams", "url": "https://example.com/teams"}]}"##;
+        let source = serde_json::from_str::<Source>(source).unwrap();
+        let destinations = source.destinations();
+        let previous = slack::MessageRef {
+            channel_id: String::from("C123"),
+            ts: String::from("1503435956.000247"),
+            destination: 0,
+        };
+
+        assert_eq!(
+            SlackNotifier::message_ref_for(Some(&previous), 0, destinations[0]),
+            Some(previous.clone())
+        );
+        assert_eq!(
+            SlackNotifier::message_ref_for(Some(&previous), 1, destinations[1]),
+            None
+        );
+        let previous = slack::MessageRef {
+            destination: 2,
+            ..previous
+        };
+        assert_eq!(
+            SlackNotifier::message_ref_for(Some(&previous), 2, destinations[2]),
+            None
+        );
+    }
+
+    #[test]
+    fn can_describe_each_destination_in_metadata() {
+        let sent = |ts: &str, channel_id: &str| message::Sent {
+            ts: Some(String::from(ts)),
+            channel_id: Some(String::from(channel_id)),
+        };
+        let results = vec![
+            DestinationResult {
+                index: 0,
+                name: String::from("teams"),
+                channel: None,
+                result: Some(Ok(message::Sent::default())),
+            },
+            DestinationResult {
+                index: 1,
+                name: String::from("slack #team-ci"),
+                channel: Some(String::from("#team-ci")),
+                result: Some(Ok(sent("1503435956.000247", "C123"))),
+            },
+            DestinationResult {
+                index: 2,
+                name: String::from("slack #releases"),
+                channel: Some(String::from("#releases")),
+                result: Some(Ok(sent("1503435957.000100", "C456"))),
+            },
+            DestinationResult {
+                index: 3,
+                name: String::from("slack #alerts"),
+                channel: Some(String::from("#alerts")),
+                result: Some(Err(String::from("channel_not_found"))),
+            },
+        ];
+
+        let metadata = SlackNotifier::out_metadata(AlertType::Success, results, true);
+        assert!(metadata.sent);
+        assert_eq!(metadata.channel, None);
+        assert_eq!(metadata.ts.as_deref(), Some("1503435956.000247"));
+        assert_eq!(metadata.channel_id.as_deref(), Some("C123"));
+        assert_eq!(metadata.destination.as_deref(), Some("1"));
+        assert_eq!(
+            metadata.destinations.as_deref(),
+            Some("teams: sent; slack #team-ci: sent to C123 at 1503435956.000247; slack #releases: sent to C456 at 1503435957.000100; slack #alerts: channel_not_found")
+        );
+        assert_eq!(
+            metadata.error.as_deref(),
+            Some("slack #alerts: channel_not_found")
+        );
+
+        let results = vec![DestinationResult {
+            index: 0,
+            name: String::from("slack #team-ci"),
+            channel: Some(String::from("#team-ci")),
+            result: None,
+        }];
+        let metadata = SlackNotifier::out_metadata(AlertType::Failed, results, false);
+        assert!(!metadata.sent);
+        assert_eq!(metadata.channel.as_deref(), Some("#team-ci"));
+        assert_eq!(metadata.destination, None);
+        assert_eq!(metadata.destinations, None);
     }
 }
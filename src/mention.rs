@@ -0,0 +1,70 @@
+use serde::Deserialize;
+
+use crate::AlertType;
+
+/// Mention a user, a user group, or everyone in the channel for some alert types.
+#[derive(Deserialize, Debug, Clone)]
+pub(crate) struct Mention {
+    mention: String,
+    alert_types: Option<Vec<AlertType>>,
+}
+
+impl Mention {
+    fn matches(&self, alert_type: &AlertType) -> bool {
+        self.alert_types
+            .as_ref()
+            .map(|alert_types| alert_types.contains(alert_type))
+            .unwrap_or(true)
+    }
+
+    fn to_slack(&self) -> String {
+        match self.mention.as_str() {
+            mention if mention.starts_with('<') => String::from(mention),
+            "here" | "channel" | "everyone" => format!("<!{}>", self.mention),
+            group if group.starts_with('S') => format!("<!subteam^{}>", group),
+            user => format!("<@{}>", user),
+        }
+    }
+}
+
+/// Mentions to add to a message with this alert type, in Slack's syntax.
+pub(crate) fn mentions_for(mentions: &[Mention], alert_type: &AlertType) -> Option<String> {
+    let mentions = mentions
+        .iter()
+        .filter(|mention| mention.matches(alert_type))
+        .map(Mention::to_slack)
+        .collect::<Vec<_>>();
+    if mentions.is_empty() {
+        None
+    } else {
+        Some(mentions.join(" "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn can_format_mentions() {
+        let mentions = serde_json::from_str::<Vec<Mention>>(
+            r#"[
+                {"mention": "U123", "alert_types": ["broke", "failed"]},
+                {"mention": "S456", "alert_types": ["failed"]},
+                {"mention": "here", "alert_types": ["errored"]},
+                {"mention": "<@W789>"}
+            ]"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            mentions_for(&mentions, &AlertType::Failed).as_deref(),
+            Some("<@U123> <!subteam^S456> <@W789>")
+        );
+        assert_eq!(
+            mentions_for(&mentions, &AlertType::Errored).as_deref(),
+            Some("<!here> <@W789>")
+        );
+        assert_eq!(mentions_for(&mentions[..3], &AlertType::Success), None);
+    }
+}
@@ -2,12 +2,72 @@ use serde::Serialize;
 
 use crate::{AlertType, OutParams};
 use concourse_resource::BuildMetadata;
+use serde_json::json;
+
+pub(crate) mod discord;
+pub(crate) mod email;
+pub(crate) mod google_chat;
+pub(crate) mod mattermost;
+pub(crate) mod rocketchat;
+pub(crate) mod teams;
+pub(crate) mod webhook;
+
+const MAX_HEADER_TEXT_LENGTH: usize = 150;
+const MAX_SECTION_TEXT_LENGTH: usize = 3000;
+const MAX_COMMIT_MESSAGE_LENGTH: usize = 100;
 
 #[derive(Serialize)]
 pub struct Message {
     pub color: String,
     pub text: Option<String>,
     pub icon_url: String,
+    #[serde(skip)]
+    pub thread: Option<crate::slack::MessageRef>,
+    pub mentions: Option<String>,
+    #[serde(skip)]
+    pub commit: Option<crate::git::Commit>,
+    #[serde(skip)]
+    pub build: Option<crate::concourse::Build>,
+    #[serde(skip)]
+    pub failed_step: Option<crate::concourse::FailedStep>,
+    #[serde(skip)]
+    pub streak: Option<crate::concourse::Streak>,
+}
+
+/// A message delivered by a backend, only Slack API calls identify it.
+#[derive(Debug, Default)]
+pub(crate) struct Sent {
+    pub(crate) ts: Option<String>,
+    pub(crate) channel_id: Option<String>,
+}
+
+/// POST `message` to an incoming webhook, that does not identify the message it posted.
+pub(crate) fn send_to_webhook<T: Serialize>(
+    client: &crate::http::Client,
+    url: &str,
+    message: &T,
+) -> Result<Sent, String> {
+    client.post_json(url, None, message)?;
+    Ok(Sent::default())
+}
+
+/// A message rendered for the backend of a destination.
+#[derive(Debug)]
+pub(crate) enum Payload {
+    Json(serde_json::Value),
+    Email(email::Email),
+}
+
+#[derive(Serialize, Debug)]
+pub struct SlackMessage {
+    #[serde(flatten)]
+    pub message: slack_push::Message,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub blocks: Option<Vec<serde_json::Value>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thread_ts: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reply_broadcast: Option<bool>,
 }
 
 struct FormattedBuildInfo {
@@ -17,6 +77,46 @@ struct FormattedBuildInfo {
     build_url: Option<String>,
 }
 
+/// What every backend shows of a message depending on `params.mode`, see
+/// [`FormattedBuildInfo::content`].
+struct Content {
+    title: String,
+    text: Option<String>,
+    fields: Option<Vec<(String, String)>>,
+}
+
+impl FormattedBuildInfo {
+    /// The build name followed by the alert type, the title of most messages.
+    fn title(&self, params: &OutParams) -> String {
+        format!("{} - {}", self.build_name, params.alert_type.message())
+    }
+
+    /// In concise mode the text of the message is its title, otherwise `title` is followed by the
+    /// text, and by the job and build fields in normal_with_info mode.
+    fn content(&self, text: Option<String>, params: &OutParams, title: String) -> Content {
+        match params.mode {
+            crate::Mode::Concise => Content {
+                title: text.unwrap_or_else(|| self.build_name.clone()),
+                text: None,
+                fields: None,
+            },
+            crate::Mode::Normal => Content {
+                title,
+                text,
+                fields: None,
+            },
+            crate::Mode::NormalWithInfo => Content {
+                title,
+                text,
+                fields: Some(vec![
+                    (String::from("Job"), self.job_name.clone()),
+                    (String::from("Build"), self.build_number.clone()),
+                ]),
+            },
+        }
+    }
+}
+
 fn formatted_build_info_from_params(build_metadata: &BuildMetadata) -> FormattedBuildInfo {
     if let (Some(pipeline_name), Some(job_name), Some(name)) = (
         build_metadata.pipeline_name.as_ref(),
@@ -46,55 +146,101 @@ fn formatted_build_info_from_params(build_metadata: &BuildMetadata) -> Formatted
     }
 }
 
+/// Value of a variable that can be used in message texts, from the build metadata or the environment.
+pub(crate) fn build_variable(name: &str, build_metadata: &BuildMetadata) -> Option<String> {
+    match name {
+        "BUILD_ID" => Some(build_metadata.id.clone()),
+        "BUILD_NAME" => build_metadata.name.clone(),
+        "BUILD_JOB_NAME" => build_metadata.job_name.clone(),
+        "BUILD_PIPELINE_NAME" => build_metadata.pipeline_name.clone(),
+        "BUILD_TEAM_NAME" => Some(build_metadata.team_name.clone()),
+        "ATC_EXTERNAL_URL" => Some(build_metadata.atc_external_url.clone()),
+        "BUILD_URL" => formatted_build_info_from_params(build_metadata).build_url,
+        _ => std::env::var(name).ok(),
+    }
+}
+
+/// Read a complete Slack message from a file, to be sent as is apart from the channel.
+pub(crate) fn payload_from_file(
+    file: &str,
+    params: &OutParams,
+    input_path: &str,
+    build_metadata: &BuildMetadata,
+    thread: Option<&crate::slack::MessageRef>,
+) -> Result<serde_json::Value, String> {
+    let mut path = std::path::PathBuf::new();
+    path.push(input_path);
+    path.push(file);
+    let mut content = std::fs::read_to_string(path)
+        .map_err(|err| format!("error reading file {}: {}", file, err))?;
+    if params.interpolate_payload {
+        // values are escaped so that they can be used inside JSON strings
+        content = crate::interpolation::interpolate(&content, |variable| {
+            build_variable(variable, build_metadata)
+                .and_then(|value| serde_json::to_string(&value).ok())
+                .map(|value| String::from(&value[1..value.len() - 1]))
+        });
+    }
+    let mut payload = serde_json::from_str::<serde_json::Value>(&content)
+        .map_err(|err| format!("error parsing file {}: {}", file, err))?;
+    if !payload.is_object() {
+        return Err(format!("file {} does not contain a JSON object", file));
+    }
+    if let Some(channel) = params.channel.as_ref() {
+        payload["channel"] = serde_json::Value::from(channel.as_str());
+    }
+    if let Some(thread) = thread {
+        payload["channel"] = serde_json::Value::from(thread.channel_id.as_str());
+        payload["thread_ts"] = serde_json::Value::from(thread.ts.as_str());
+        if params.thread_broadcast {
+            payload["reply_broadcast"] = serde_json::Value::from(true);
+        }
+    }
+    Ok(payload)
+}
+
+/// The message to reply to from `thread_from`, if its directory contains one.
+pub(crate) fn thread_from(
+    params: &OutParams,
+    input_path: &str,
+) -> Option<crate::slack::MessageRef> {
+    let thread_from = params.thread_from.as_ref()?;
+    let mut path = std::path::PathBuf::new();
+    path.push(input_path);
+    path.push(thread_from);
+    crate::slack::MessageRef::read_from(&path).ok()
+}
+
 impl Message {
-    pub(crate) fn new(params: &OutParams, input_path: &str) -> Message {
-        let mut message = match params.alert_type {
-            AlertType::Success | AlertType::Fixed => Message {
-                color: String::from("#11c560"),
-                icon_url: String::from(
-                    "https://ci.concourse-ci.org/public/images/favicon-succeeded.png",
-                ),
-                text: None,
-            },
-            AlertType::Failed | AlertType::Broke => Message {
-                color: String::from("#ed4b35"),
-                icon_url: String::from(
-                    "https://ci.concourse-ci.org/public/images/favicon-failed.png",
-                ),
-                text: None,
-            },
-            AlertType::Started => Message {
-                color: String::from("#fad43b"),
-                icon_url: String::from(
-                    "https://ci.concourse-ci.org/public/images/favicon-started.png",
-                ),
-                text: None,
-            },
-            AlertType::Aborted => Message {
-                color: String::from("#8b572a"),
-                icon_url: String::from(
-                    "https://ci.concourse-ci.org/public/images/favicon-aborted.png",
-                ),
-                text: None,
-            },
-            AlertType::Errored => Message {
-                color: String::from("#f5a623"),
-                icon_url: String::from(
-                    "https://ci.concourse-ci.org/public/images/favicon-errored.png",
-                ),
-                text: None,
-            },
-            AlertType::Custom => Message {
-                color: String::from("#35495c"),
-                icon_url: String::from(
-                    "https://ci.concourse-ci.org/public/images/favicon-pending.png",
-                ),
-                text: None,
-            },
+    pub(crate) fn new(
+        params: &OutParams,
+        input_path: &str,
+        build_metadata: &BuildMetadata,
+        context: &crate::BuildContext,
+    ) -> Message {
+        let (color, icon) = match params.alert_type {
+            AlertType::Success | AlertType::Fixed => ("#11c560", "succeeded"),
+            AlertType::Failed | AlertType::Broke | AlertType::StillFailing => ("#ed4b35", "failed"),
+            AlertType::Started => ("#fad43b", "started"),
+            AlertType::Aborted => ("#8b572a", "aborted"),
+            AlertType::Errored => ("#f5a623", "errored"),
+            AlertType::Custom => ("#35495c", "pending"),
+        };
+        // the build, destination and git input fill in the other fields once the text is rendered
+        let mut message = Message {
+            color: params.color.clone().unwrap_or_else(|| String::from(color)),
+            text: None,
+            icon_url: format!(
+                "https://ci.concourse-ci.org/public/images/favicon-{}.png",
+                icon
+            ),
+            thread: None,
+            mentions: None,
+            commit: None,
+            build: None,
+            failed_step: None,
+            streak: None,
         };
-        if let Some(color) = params.color.as_ref() {
-            message.color = color.clone();
-        }
         match (
             params.message_file.as_ref(),
             params.message.as_ref(),
@@ -126,6 +272,41 @@ impl Message {
             }
             (None, None, _) => {}
         }
+        let template = match (params.template.as_ref(), params.template_file.as_ref()) {
+            (Some(template), _) => Some(template.clone()),
+            (None, Some(file)) => {
+                let mut path = std::path::PathBuf::new();
+                path.push(input_path);
+                path.push(file);
+                Some(
+                    std::fs::read_to_string(path)
+                        .unwrap_or_else(|_| format!("error reading file {}", file)),
+                )
+            }
+            (None, None) => None,
+        };
+        if let Some(template) = template {
+            message.text = Some(
+                crate::template::Context::new(
+                    build_metadata,
+                    formatted_build_info_from_params(build_metadata).build_url,
+                    &params.alert_type,
+                    context.current_build.as_ref(),
+                    context.previous_build.as_ref(),
+                    context.streak.as_ref(),
+                )
+                .load_data(input_path, &params.template_data)
+                .and_then(|context| crate::template::render(&template, &context))
+                .unwrap_or_else(|err| err),
+            );
+        } else {
+            // templates already have the build metadata, and their data must be left as is
+            message.text = message.text.map(|text| {
+                crate::interpolation::interpolate(&text, |variable| {
+                    build_variable(variable, build_metadata)
+                })
+            });
+        }
         if params.message_as_code {
             message.text = message.text.map(|text| format!("```{}```", text));
         }
@@ -133,46 +314,120 @@ impl Message {
     }
 
     pub(crate) fn into_slack_message(
-        self,
+        mut self,
         build_metadata: BuildMetadata,
         params: &OutParams,
-    ) -> slack_push::Message {
+    ) -> SlackMessage {
         let formatted_build_info = formatted_build_info_from_params(&build_metadata);
+        let thread = self.thread.take();
+        let reply_broadcast = if thread.is_some() && params.thread_broadcast {
+            Some(true)
+        } else {
+            None
+        };
+        // mentions in attachments don't notify, they are sent in the text of the message instead
+        let mentions = self.mentions.take();
+        let (mut message, blocks) = match params.format {
+            crate::Format::Attachments => {
+                let mut message = self.into_attachments(formatted_build_info, params);
+                message.text = mentions;
+                (message, None)
+            }
+            crate::Format::Blocks => {
+                let fallback = format!(
+                    "{} - {}",
+                    formatted_build_info.build_name,
+                    params.alert_type.message()
+                );
+                let mut blocks = self.into_blocks(formatted_build_info, params);
+                if let Some(mentions) = mentions {
+                    blocks.insert(
+                        0,
+                        json!({
+                            "type": "section",
+                            "text": { "type": "mrkdwn", "text": mentions },
+                        }),
+                    );
+                }
+                (
+                    slack_push::Message {
+                        text: Some(fallback),
+                        channel: params.channel.clone(),
+                        ..Default::default()
+                    },
+                    Some(blocks),
+                )
+            }
+        };
+        if let Some(thread) = thread.as_ref() {
+            // the reply must be in the channel of the message it replies to
+            message.channel = Some(thread.channel_id.clone());
+        }
+        SlackMessage {
+            message,
+            blocks,
+            thread_ts: thread.map(|thread| thread.ts),
+            reply_broadcast,
+        }
+    }
+
+    /// The failing streak is shown for `still_failing` alerts, other alerts only fetch it for
+    /// templates.
+    fn streak_text(&self, params: &OutParams) -> Option<String> {
+        if params.alert_type == AlertType::StillFailing {
+            self.streak.as_ref().map(streak_text)
+        } else {
+            None
+        }
+    }
+
+    fn into_attachments(
+        self,
+        formatted_build_info: FormattedBuildInfo,
+        params: &OutParams,
+    ) -> slack_push::Message {
+        let streak = self.streak_text(params);
+        let (build, commit) = (self.build.as_ref(), self.commit.as_ref());
+        let content =
+            formatted_build_info.content(self.text, params, formatted_build_info.title(params));
         slack_push::Message {
             attachments: Some(vec![slack_push::message::Attachment {
                 author_name: match params.mode {
-                    crate::Mode::Concise => {
-                        Some(self.text.clone().unwrap_or(formatted_build_info.build_name))
-                    }
-                    crate::Mode::Normal | crate::Mode::NormalWithInfo => Some(format!(
-                        "{} - {}",
-                        formatted_build_info.build_name,
-                        params.alert_type.message()
-                    )),
+                    crate::Mode::Concise => Some(with_streak(content.title, streak.as_deref())),
+                    crate::Mode::Normal | crate::Mode::NormalWithInfo => Some(content.title),
                 },
                 text: match params.mode {
                     crate::Mode::Concise => None,
-                    crate::Mode::Normal | crate::Mode::NormalWithInfo => self.text,
+                    crate::Mode::Normal | crate::Mode::NormalWithInfo => {
+                        let lines = content
+                            .text
+                            .into_iter()
+                            .chain(streak.map(|streak| format!("*Streak:* {}", streak)))
+                            .chain(self.failed_step.as_ref().map(failed_step_text))
+                            .collect::<Vec<_>>();
+                        if lines.is_empty() {
+                            None
+                        } else {
+                            Some(lines.join("\n"))
+                        }
+                    }
                 },
                 mrkdwn_in: Some(vec![String::from("text")]),
                 color: Some(self.color),
                 footer: formatted_build_info.build_url,
                 footer_icon: Some(self.icon_url),
-                fields: match params.mode {
-                    crate::Mode::Concise | crate::Mode::Normal => None,
-                    crate::Mode::NormalWithInfo => Some(vec![
-                        slack_push::message::AttachmentField {
-                            title: Some(String::from("Job")),
-                            value: Some(formatted_build_info.job_name),
+                fields: content.fields.map(|fields| {
+                    fields
+                        .into_iter()
+                        .chain(timing_fields(build, now()))
+                        .chain(commit_fields(commit))
+                        .map(|(title, value)| slack_push::message::AttachmentField {
+                            title: Some(title),
+                            value: Some(value),
                             short: Some(true),
-                        },
-                        slack_push::message::AttachmentField {
-                            title: Some(String::from("Build")),
-                            value: Some(formatted_build_info.build_number),
-                            short: Some(true),
-                        },
-                    ]),
-                },
+                        })
+                        .collect()
+                }),
                 ..Default::default()
             }]),
             channel: params.channel.clone(),
@@ -180,4 +435,584 @@ impl Message {
             ..Default::default()
         }
     }
+
+    fn into_blocks(
+        self,
+        formatted_build_info: FormattedBuildInfo,
+        params: &OutParams,
+    ) -> Vec<serde_json::Value> {
+        let streak = self.streak_text(params);
+        let content =
+            formatted_build_info.content(self.text, params, formatted_build_info.title(params));
+        let mut blocks = vec![];
+        match params.mode {
+            crate::Mode::Concise => {
+                blocks.push(json!({
+                    "type": "section",
+                    "text": {
+                        "type": "mrkdwn",
+                        "text": truncate(
+                            &with_streak(content.title, streak.as_deref()),
+                            MAX_SECTION_TEXT_LENGTH
+                        ),
+                    },
+                }));
+            }
+            crate::Mode::Normal | crate::Mode::NormalWithInfo => {
+                blocks.push(json!({
+                    "type": "header",
+                    "text": {
+                        "type": "plain_text",
+                        "text": truncate(&content.title, MAX_HEADER_TEXT_LENGTH),
+                    },
+                }));
+                if let Some(text) = content.text {
+                    blocks.push(json!({
+                        "type": "section",
+                        "text": {
+                            "type": "mrkdwn",
+                            "text": truncate(&text, MAX_SECTION_TEXT_LENGTH),
+                        },
+                    }));
+                }
+                if let Some(streak) = streak {
+                    blocks.push(json!({
+                        "type": "section",
+                        "text": {
+                            "type": "mrkdwn",
+                            "text": format!("*Streak:* {}", streak),
+                        },
+                    }));
+                }
+                if let Some(failed_step) = self.failed_step.as_ref() {
+                    blocks.push(json!({
+                        "type": "section",
+                        "text": {
+                            "type": "mrkdwn",
+                            "text": failed_step_text(failed_step),
+                        },
+                    }));
+                }
+                if let Some(fields) = content.fields {
+                    let fields = fields
+                        .into_iter()
+                        .chain(timing_fields(self.build.as_ref(), now()))
+                        .chain(commit_fields(self.commit.as_ref()))
+                        .map(|(title, value)| {
+                            json!({
+                                "type": "mrkdwn",
+                                "text": format!("*{}*\n{}", title, value),
+                            })
+                        })
+                        .collect::<Vec<_>>();
+                    blocks.push(json!({
+                        "type": "section",
+                        "fields": fields,
+                    }));
+                }
+                blocks.push(json!({
+                    "type": "context",
+                    "elements": [
+                        {
+                            "type": "image",
+                            "image_url": self.icon_url,
+                            "alt_text": params.alert_type.message(),
+                        },
+                        {
+                            "type": "mrkdwn",
+                            "text": params.alert_type.message(),
+                        },
+                    ],
+                }));
+            }
+        }
+        if let Some(build_url) = formatted_build_info.build_url {
+            blocks.push(json!({
+                "type": "actions",
+                "elements": [
+                    {
+                        "type": "button",
+                        "text": {
+                            "type": "plain_text",
+                            "text": "View build",
+                        },
+                        "url": build_url,
+                    },
+                ],
+            }));
+        }
+        blocks
+    }
+}
+
+/// The name and logs of the step that failed, with the logs truncated from their start to fit in a
+/// section.
+fn failed_step_text(failed_step: &crate::concourse::FailedStep) -> String {
+    let header = format!("*Failed step:* `{}`", failed_step.name);
+    if failed_step.log.is_empty() {
+        return header;
+    }
+    // header, new lines and code block delimiters
+    let max_log_length = MAX_SECTION_TEXT_LENGTH.saturating_sub(header.chars().count() + 9);
+    let log_length = failed_step.log.chars().count();
+    let log = if log_length <= max_log_length {
+        failed_step.log.clone()
+    } else {
+        let mut log = String::from("…");
+        log.extend(
+            failed_step
+                .log
+                .chars()
+                .skip(log_length - max_log_length.saturating_sub(1)),
+        );
+        log
+    };
+    format!("{}\n```\n{}\n```", header, log)
+}
+
+fn streak_text(streak: &crate::concourse::Streak) -> String {
+    if streak.truncated {
+        format!(
+            "failing for at least {} builds since #{}",
+            streak.count, streak.since
+        )
+    } else if streak.count == 1 {
+        String::from("failing for 1 build")
+    } else {
+        format!(
+            "failing for {} builds since #{}",
+            streak.count, streak.since
+        )
+    }
+}
+
+fn with_streak(text: String, streak: Option<&str>) -> String {
+    match streak {
+        Some(streak) => format!("{}, {}", text, streak),
+        None => text,
+    }
+}
+
+/// Fields with the start time and duration of the build, in Slack's mrkdwn.
+fn timing_fields(build: Option<&crate::concourse::Build>, now: i64) -> Vec<(String, String)> {
+    let start_time = match build.and_then(|build| build.start_time) {
+        Some(start_time) => start_time,
+        None => return vec![],
+    };
+    let end_time = build.and_then(|build| build.end_time).unwrap_or(now);
+    vec![
+        (
+            String::from("Started"),
+            format!(
+                "<!date^{}^{{date_short_pretty}} at {{time_secs}}|{}>",
+                start_time, start_time
+            ),
+        ),
+        (
+            String::from("Duration"),
+            format_duration(end_time - start_time),
+        ),
+    ]
+}
+
+fn now() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|duration| duration.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+fn format_duration(seconds: i64) -> String {
+    let seconds = seconds.max(0);
+    match (seconds / 3600, seconds % 3600 / 60, seconds % 60) {
+        (0, 0, s) => format!("{}s", s),
+        (0, m, s) => format!("{}m {}s", m, s),
+        (h, m, s) => format!("{}h {}m {}s", h, m, s),
+    }
+}
+
+/// Fields describing the commit being built, in Slack's mrkdwn.
+fn commit_fields(commit: Option<&crate::git::Commit>) -> Vec<(String, String)> {
+    let commit = match commit {
+        Some(commit) => commit,
+        None => return vec![],
+    };
+    let mut fields = vec![];
+    if let Some(short_sha) = commit.short_sha.as_ref() {
+        fields.push((
+            String::from("Commit"),
+            match commit.url() {
+                Some(url) => format!("<{}|{}>", url, short_sha),
+                None => short_sha.clone(),
+            },
+        ));
+    }
+    if let Some(branch) = commit.branch.as_ref() {
+        fields.push((String::from("Branch"), escape_mrkdwn(branch)));
+    }
+    if let Some(author) = commit.author_name.as_ref().or(commit.author_email.as_ref()) {
+        fields.push((String::from("Author"), escape_mrkdwn(author)));
+    }
+    if let Some(message) = commit.message.as_ref() {
+        fields.push((
+            String::from("Message"),
+            escape_mrkdwn(&truncate(message, MAX_COMMIT_MESSAGE_LENGTH)),
+        ));
+    }
+    fields
+}
+
+/// Escape the characters used by Slack for links and mentions.
+fn escape_mrkdwn(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+fn truncate(text: &str, max_length: usize) -> String {
+    if text.chars().count() <= max_length {
+        String::from(text)
+    } else if max_length == 0 {
+        String::new()
+    } else {
+        let mut truncated = text.chars().take(max_length - 1).collect::<String>();
+        truncated.push('…');
+        truncated
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    pub(crate) fn build_metadata() -> BuildMetadata {
+        BuildMetadata {
+            id: String::from("1234"),
+            name: String::from("42").into(),
+            job_name: String::from("unit tests").into(),
+            pipeline_name: String::from("ci").into(),
+            team_name: String::from("main"),
+            atc_external_url: String::from("https://ci.example.com"),
+        }
+    }
+
+    pub(crate) fn out_params(params: serde_json::Value) -> OutParams {
+        serde_json::from_value(params).unwrap()
+    }
+
+    /// An input directory with the output of a previous `put`, replied to with `thread_from`.
+    fn input_with_previous_message(name: &str) -> String {
+        let path = std::env::temp_dir().join(name);
+        std::fs::create_dir_all(path.join("notify")).unwrap();
+        crate::slack::MessageRef {
+            channel_id: String::from("C123"),
+            ts: String::from("1503435956.000247"),
+            destination: 0,
+        }
+        .write_to(&path.join("notify"))
+        .unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn does_not_interpolate_templates() {
+        let input_path = std::env::temp_dir().join("slack-notifier-template-data");
+        std::fs::create_dir_all(&input_path).unwrap();
+        std::fs::write(
+            input_path.join("commit.json"),
+            r#"{"message": "costs $$5, uses ${HOME}"}"#,
+        )
+        .unwrap();
+        let params = out_params(json!({
+            "template": "{{data.commit.message}} in {{build.job_name}}",
+            "template_data": { "commit": "commit.json" },
+        }));
+        let message = Message::new(
+            &params,
+            &input_path.to_string_lossy(),
+            &build_metadata(),
+            &crate::BuildContext::default(),
+        );
+        assert_eq!(
+            message.text.as_deref(),
+            Some("costs $$5, uses ${HOME} in unit tests")
+        );
+    }
+
+    #[test]
+    fn can_render_current_build_in_template() {
+        let params = out_params(json!({
+            "template": "#{{build.name}}{{#if build.rerun_of}} rerun of #{{build.rerun_of.name}}{{/if}} by {{build.created_by}}",
+        }));
+        let context = crate::BuildContext {
+            current_build: serde_json::from_value(json!({
+                "id": 1234,
+                "team_name": "main",
+                "name": "42.1",
+                "status": "started",
+                "api_url": "/api/v1/builds/1234",
+                "created_by": "jane",
+                "rerun_number": 1,
+                "rerun_of": { "id": 1200, "name": "42" },
+            }))
+            .unwrap(),
+            ..Default::default()
+        };
+        let message = Message::new(&params, "", &build_metadata(), &context);
+        assert_eq!(message.text.as_deref(), Some("#42 rerun of #42 by jane"));
+
+        let message = Message::new(
+            &params,
+            "",
+            &build_metadata(),
+            &crate::BuildContext::default(),
+        );
+        assert_eq!(message.text.as_deref(), Some("#42 by "));
+    }
+
+    #[test]
+    fn can_render_blocks() {
+        let params = out_params(json!({
+            "alert_type": "failed",
+            "format": "blocks",
+            "message": "tests are red",
+        }));
+        let message = Message::new(
+            &params,
+            "",
+            &build_metadata(),
+            &crate::BuildContext::default(),
+        );
+        let message =
+            serde_json::to_value(message.into_slack_message(build_metadata(), &params)).unwrap();
+        assert!(message["attachments"].is_null());
+        assert!(message["text"].as_str().unwrap().ends_with(" - Failed"));
+
+        let blocks = message["blocks"].as_array().unwrap();
+        assert_eq!(
+            blocks
+                .iter()
+                .map(|block| block["type"].as_str().unwrap())
+                .collect::<Vec<_>>(),
+            vec!["header", "section", "section", "context", "actions"]
+        );
+        assert_eq!(blocks[0]["text"]["type"], "plain_text");
+        assert_eq!(blocks[1]["text"]["text"], "tests are red");
+        assert_eq!(blocks[2]["fields"][0]["text"], "*Job*\nci/unit tests");
+        assert_eq!(
+            blocks[4]["elements"][0]["url"],
+            "https://ci.example.com/teams/main/pipelines/ci/jobs/unit%20tests/builds/42"
+        );
+    }
+
+    #[test]
+    fn can_reply_in_thread_of_previous_message() {
+        let input_path = input_with_previous_message("slack-notifier-thread-from");
+        let params = out_params(json!({
+            "alert_type": "failed",
+            "channel": "#other",
+            "thread_from": "notify",
+            "thread_broadcast": true,
+        }));
+        let mut message = Message::new(
+            &params,
+            &input_path,
+            &build_metadata(),
+            &crate::BuildContext::default(),
+        );
+        message.thread = thread_from(&params, &input_path);
+        let message =
+            serde_json::to_value(message.into_slack_message(build_metadata(), &params)).unwrap();
+        assert_eq!(message["thread_ts"], "1503435956.000247");
+        assert_eq!(message["reply_broadcast"], true);
+        assert_eq!(message["channel"], "C123");
+
+        let params = out_params(json!({ "alert_type": "failed" }));
+        assert_eq!(thread_from(&params, &input_path), None);
+        let message = Message::new(
+            &params,
+            &input_path,
+            &build_metadata(),
+            &crate::BuildContext::default(),
+        );
+        let message =
+            serde_json::to_value(message.into_slack_message(build_metadata(), &params)).unwrap();
+        assert!(message.get("thread_ts").is_none());
+        assert!(message.get("reply_broadcast").is_none());
+    }
+
+    #[test]
+    fn can_send_payload_file() {
+        let input_path = std::env::temp_dir().join("slack-notifier-payload-file");
+        std::fs::create_dir_all(&input_path).unwrap();
+        std::fs::write(
+            input_path.join("payload.json"),
+            r##"{"text": "${BUILD_JOB_NAME}: ${SLACK_NOTIFIER_TEST_VALUE}", "channel": "#general"}"##,
+        )
+        .unwrap();
+        std::env::set_var("SLACK_NOTIFIER_TEST_VALUE", "say \"hi\"\n");
+        let params = out_params(json!({
+            "payload_file": "payload.json",
+            "interpolate_payload": true,
+            "channel": "#ci",
+        }));
+        let payload = payload_from_file(
+            "payload.json",
+            &params,
+            &input_path.to_string_lossy(),
+            &build_metadata(),
+            None,
+        )
+        .unwrap();
+        assert_eq!(
+            payload,
+            json!({"text": "unit tests: say \"hi\"\n", "channel": "#ci"})
+        );
+    }
+
+    #[test]
+    fn can_reply_in_thread_with_payload_file() {
+        let input_path = input_with_previous_message("slack-notifier-thread-from-payload");
+        std::fs::write(
+            std::path::Path::new(&input_path).join("payload.json"),
+            r#"{"text": "hello"}"#,
+        )
+        .unwrap();
+        let params = out_params(json!({
+            "payload_file": "payload.json",
+            "thread_from": "notify",
+        }));
+        let thread = thread_from(&params, &input_path);
+        let payload = payload_from_file(
+            "payload.json",
+            &params,
+            &input_path,
+            &build_metadata(),
+            thread.as_ref(),
+        )
+        .unwrap();
+        assert_eq!(
+            payload,
+            json!({"text": "hello", "channel": "C123", "thread_ts": "1503435956.000247"})
+        );
+    }
+
+    #[test]
+    fn can_format_duration() {
+        assert_eq!(format_duration(45), "45s");
+        assert_eq!(format_duration(125), "2m 5s");
+        assert_eq!(format_duration(3725), "1h 2m 5s");
+        assert_eq!(format_duration(-3), "0s");
+    }
+
+    #[test]
+    fn can_describe_commit() {
+        let commit = crate::git::Commit {
+            sha: Some(String::from("8c1a2f4e5d6b7a8c9d0e1f2a3b4c5d6e7f8a9b0c")),
+            short_sha: Some(String::from("8c1a2f4")),
+            branch: Some(String::from("feature/<new>")),
+            author_name: Some(String::from("Jane & John")),
+            author_email: None,
+            message: Some(format!("Merge <@U123> {}", "a".repeat(200))),
+            repository_url: Some(String::from("https://github.com/org/repo")),
+        };
+        let fields = commit_fields(Some(&commit));
+        assert_eq!(
+            fields[0],
+            (
+                String::from("Commit"),
+                String::from(
+                    "<https://github.com/org/repo/commit/8c1a2f4e5d6b7a8c9d0e1f2a3b4c5d6e7f8a9b0c|8c1a2f4>"
+                )
+            )
+        );
+        assert_eq!(fields[1].1, "feature/&lt;new&gt;");
+        assert_eq!(fields[2].1, "Jane &amp; John");
+        assert!(fields[3].1.starts_with("Merge &lt;@U123&gt; aaa"));
+        assert!(fields[3].1.ends_with('…'));
+        assert!(commit_fields(None).is_empty());
+    }
+
+    #[test]
+    fn can_describe_streak() {
+        let streak = crate::concourse::Streak {
+            count: 7,
+            since: String::from("123"),
+            truncated: false,
+        };
+        assert_eq!(streak_text(&streak), "failing for 7 builds since #123");
+        let streak = crate::concourse::Streak {
+            truncated: true,
+            ..streak
+        };
+        assert_eq!(
+            streak_text(&streak),
+            "failing for at least 7 builds since #123"
+        );
+    }
+
+    #[test]
+    fn can_show_streak_in_all_modes() {
+        let streak = crate::concourse::Streak {
+            count: 7,
+            since: String::from("123"),
+            truncated: false,
+        };
+        let render = |params: &OutParams| {
+            let mut message = Message::new(
+                params,
+                "",
+                &build_metadata(),
+                &crate::BuildContext::default(),
+            );
+            message.streak = Some(streak.clone());
+            serde_json::to_value(message.into_slack_message(build_metadata(), params)).unwrap()
+        };
+
+        let message = render(&out_params(json!({
+            "alert_type": "still_failing",
+            "mode": "concise",
+        })));
+        assert_eq!(
+            message["attachments"][0]["author_name"],
+            "ci/unit tests #42, failing for 7 builds since #123"
+        );
+
+        let message = render(&out_params(json!({
+            "alert_type": "still_failing",
+            "mode": "normal",
+            "message": "tests are red",
+        })));
+        assert_eq!(
+            message["attachments"][0]["text"],
+            "tests are red\n*Streak:* failing for 7 builds since #123"
+        );
+
+        let message = render(&out_params(json!({
+            "alert_type": "still_failing",
+            "format": "blocks",
+        })));
+        assert_eq!(
+            message["blocks"][1]["text"]["text"],
+            "*Streak:* failing for 7 builds since #123"
+        );
+
+        let message = render(&out_params(json!({
+            "alert_type": "failed",
+            "mode": "normal",
+        })));
+        assert!(message["attachments"][0]["text"].is_null());
+    }
+
+    #[test]
+    fn can_truncate_failed_step_log() {
+        let failed_step = crate::concourse::FailedStep {
+            name: String::from("unit-tests"),
+            log: format!("{}end", "a".repeat(5000)),
+        };
+        let text = failed_step_text(&failed_step);
+        assert!(text.starts_with("*Failed step:* `unit-tests`\n```\n…aaa"));
+        assert!(text.ends_with("aaend\n```"));
+        assert_eq!(text.chars().count(), MAX_SECTION_TEXT_LENGTH);
+    }
 }
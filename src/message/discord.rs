@@ -0,0 +1,191 @@
+use serde::Serialize;
+
+use super::{formatted_build_info_from_params, truncate, Message};
+use crate::OutParams;
+use concourse_resource::BuildMetadata;
+
+const MAX_TITLE_LENGTH: usize = 256;
+const MAX_DESCRIPTION_LENGTH: usize = 4096;
+const MAX_FIELD_VALUE_LENGTH: usize = 1024;
+const MAX_EMBED_LENGTH: usize = 6000;
+
+#[derive(Serialize, Debug)]
+pub struct DiscordMessage {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    username: Option<String>,
+    avatar_url: String,
+    embeds: Vec<Embed>,
+}
+
+#[derive(Serialize, Debug)]
+struct Embed {
+    title: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    color: Option<u32>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    fields: Vec<Field>,
+    footer: Footer,
+}
+
+#[derive(Serialize, Debug)]
+struct Field {
+    name: String,
+    value: String,
+    inline: bool,
+}
+
+#[derive(Serialize, Debug)]
+struct Footer {
+    text: String,
+    icon_url: String,
+}
+
+/// Discord colors are RGB integers, other values are rejected.
+fn color_to_int(color: &str) -> Option<u32> {
+    u32::from_str_radix(color.trim_start_matches('#'), 16)
+        .ok()
+        .filter(|color| *color <= 0xffffff)
+}
+
+impl Message {
+    pub(crate) fn into_discord_message(
+        self,
+        build_metadata: BuildMetadata,
+        params: &OutParams,
+        username: Option<&String>,
+    ) -> DiscordMessage {
+        let formatted_build_info = formatted_build_info_from_params(&build_metadata);
+        let content =
+            formatted_build_info.content(self.text, params, formatted_build_info.title(params));
+        let title = truncate(&content.title, MAX_TITLE_LENGTH);
+        let fields = content
+            .fields
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(name, value)| Field {
+                name,
+                value: truncate(&value, MAX_FIELD_VALUE_LENGTH),
+                inline: true,
+            })
+            .collect::<Vec<_>>();
+        let footer = Footer {
+            text: String::from(params.alert_type.message()),
+            icon_url: self.icon_url.clone(),
+        };
+        // all texts of an embed together must fit in `MAX_EMBED_LENGTH`
+        let used_length = title.chars().count()
+            + footer.text.chars().count()
+            + fields
+                .iter()
+                .map(|field| field.name.chars().count() + field.value.chars().count())
+                .sum::<usize>();
+        let description = content.text.map(|text| {
+            truncate(
+                &text,
+                MAX_DESCRIPTION_LENGTH.min(MAX_EMBED_LENGTH.saturating_sub(used_length)),
+            )
+        });
+        DiscordMessage {
+            username: username.cloned(),
+            avatar_url: self.icon_url,
+            embeds: vec![Embed {
+                title,
+                description,
+                url: formatted_build_info.build_url,
+                color: color_to_int(&self.color),
+                fields,
+                footer,
+            }],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::tests::{build_metadata, out_params};
+
+    #[test]
+    fn can_convert_color() {
+        assert_eq!(color_to_int("#11c560"), Some(0x11c560));
+        assert_eq!(color_to_int("ed4b35"), Some(0xed4b35));
+        assert_eq!(color_to_int("#ffffff"), Some(0xffffff));
+        assert_eq!(color_to_int("#1000000"), None);
+        assert_eq!(color_to_int("#ffffffff"), None);
+        assert_eq!(color_to_int("red"), None);
+    }
+
+    fn render(params: &OutParams, build_metadata: BuildMetadata) -> serde_json::Value {
+        let message = Message::new(params, "", &build_metadata, &crate::BuildContext::default());
+        serde_json::to_value(message.into_discord_message(
+            build_metadata,
+            params,
+            Some(&String::from("ci")),
+        ))
+        .unwrap()
+    }
+
+    fn length(value: &serde_json::Value) -> usize {
+        value.as_str().unwrap().chars().count()
+    }
+
+    #[test]
+    fn can_render_discord_embed() {
+        let params = out_params(serde_json::json!({
+            "alert_type": "failed",
+            "message": "a".repeat(5000),
+        }));
+        let message = render(&params, build_metadata());
+        assert_eq!(message["username"], "ci");
+        let embed = &message["embeds"][0];
+        assert_eq!(embed["title"], "ci/unit tests #42 - Failed");
+        assert_eq!(
+            embed["url"],
+            "https://ci.example.com/teams/main/pipelines/ci/jobs/unit%20tests/builds/42"
+        );
+        assert_eq!(embed["color"], 0xed4b35);
+        assert_eq!(embed["fields"][0]["name"], "Job");
+        assert_eq!(embed["fields"][0]["value"], "ci/unit tests");
+        assert_eq!(embed["fields"][1]["name"], "Build");
+        assert_eq!(embed["fields"][1]["value"], "#42");
+        assert_eq!(embed["footer"]["text"], "Failed");
+        assert_eq!(embed["footer"]["icon_url"], message["avatar_url"]);
+        assert_eq!(length(&embed["description"]), MAX_DESCRIPTION_LENGTH);
+        assert!(embed["description"].as_str().unwrap().ends_with('…'));
+    }
+
+    #[test]
+    fn can_fit_discord_embed_limits() {
+        let params = out_params(serde_json::json!({
+            "alert_type": "failed",
+            "message": "a".repeat(5000),
+        }));
+        let mut build_metadata = build_metadata();
+        build_metadata.job_name = Some("j".repeat(2000));
+        build_metadata.name = Some("4".repeat(2000));
+        let message = render(&params, build_metadata);
+        let embed = &message["embeds"][0];
+        assert_eq!(length(&embed["title"]), MAX_TITLE_LENGTH);
+        assert_eq!(length(&embed["fields"][0]["value"]), MAX_FIELD_VALUE_LENGTH);
+        assert_eq!(length(&embed["fields"][1]["value"]), MAX_FIELD_VALUE_LENGTH);
+        let fields_length = embed["fields"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|field| length(&field["name"]) + length(&field["value"]))
+            .sum::<usize>();
+        let description_length = length(&embed["description"]);
+        assert!(description_length < MAX_DESCRIPTION_LENGTH);
+        assert_eq!(
+            length(&embed["title"])
+                + length(&embed["footer"]["text"])
+                + fields_length
+                + description_length,
+            MAX_EMBED_LENGTH
+        );
+    }
+}
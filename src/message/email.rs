@@ -0,0 +1,234 @@
+use serde::{Deserialize, Serialize};
+
+use super::{formatted_build_info_from_params, Content, Message};
+use crate::{OutParams, SmtpConfiguration};
+use concourse_resource::BuildMetadata;
+
+const DEFAULT_SMTP_PORT: u16 = 587;
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Email {
+    subject: String,
+    text: String,
+    html: String,
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+impl Message {
+    pub(crate) fn into_email(self, build_metadata: BuildMetadata, params: &OutParams) -> Email {
+        let formatted_build_info = formatted_build_info_from_params(&build_metadata);
+        let subject = format!(
+            "[{}] {}",
+            params.alert_type.message(),
+            formatted_build_info.build_name
+        );
+        let Content {
+            title,
+            text,
+            fields,
+        } = formatted_build_info.content(self.text, params, subject.clone());
+        let fields = fields.unwrap_or_default();
+
+        let mut plain = vec![title.clone()];
+        plain.extend(text.clone());
+        plain.extend(
+            fields
+                .iter()
+                .map(|(name, value)| format!("{}: {}", name, value)),
+        );
+        plain.extend(formatted_build_info.build_url.clone());
+
+        let mut html = format!(
+            r#"<div style="border-left: 4px solid {}; padding-left: 12px;"><p><img src="{}" width="16" height="16"> <strong>{}</strong></p>"#,
+            escape_html(&self.color),
+            escape_html(&self.icon_url),
+            escape_html(&title)
+        );
+        if let Some(text) = text {
+            html.push_str(&format!(
+                r#"<pre style="white-space: pre-wrap;">{}</pre>"#,
+                escape_html(&text)
+            ));
+        }
+        for (name, value) in fields {
+            html.push_str(&format!(
+                "<p><strong>{}</strong>: {}</p>",
+                escape_html(&name),
+                escape_html(&value)
+            ));
+        }
+        if let Some(build_url) = formatted_build_info.build_url {
+            html.push_str(&format!(
+                r#"<p><a href="{}">View build</a></p>"#,
+                escape_html(&build_url)
+            ));
+        }
+        html.push_str("</div>");
+
+        Email {
+            subject,
+            text: plain.join("\n\n"),
+            html,
+        }
+    }
+}
+
+pub(crate) fn send(
+    client: &crate::http::Client,
+    configuration: &SmtpConfiguration,
+    email: &Email,
+) -> Result<super::Sent, String> {
+    let mut builder = lettre::Message::builder()
+        .from(
+            configuration
+                .from
+                .parse()
+                .map_err(|err| format!("invalid from address {}: {}", configuration.from, err))?,
+        )
+        .subject(email.subject.as_str());
+    for to in &configuration.to {
+        builder = builder.to(to
+            .parse()
+            .map_err(|err| format!("invalid to address {}: {}", to, err))?);
+    }
+    let email = builder
+        .multipart(lettre::message::MultiPart::alternative_plain_html(
+            email.text.clone(),
+            email.html.clone(),
+        ))
+        .map_err(|err| format!("{}", err))?;
+
+    let mut transport = if configuration.starttls.unwrap_or(true) {
+        lettre::SmtpTransport::starttls_relay(&configuration.host)
+            .map_err(|err| format!("{}", err))?
+    } else {
+        lettre::SmtpTransport::builder_dangerous(&configuration.host)
+    }
+    .port(configuration.port.unwrap_or(DEFAULT_SMTP_PORT));
+    if let (Some(username), Some(password)) = (
+        configuration.username.as_ref(),
+        configuration.password.as_ref(),
+    ) {
+        transport =
+            transport.credentials(lettre::transport::smtp::authentication::Credentials::new(
+                username.clone(),
+                password.clone(),
+            ));
+    }
+
+    let transport = transport.build();
+    // permanent errors are rejections from the server, there is no point retrying them
+    client.retry(|| {
+        lettre::Transport::send(&transport, &email)
+            .map_err(|err| (format!("{}", err), !err.is_permanent()))
+    })?;
+    Ok(super::Sent::default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A minimal SMTP server accepting one email per connection, the first `busy` connections are
+    /// refused with a transient error. Returns the port and the received emails.
+    fn smtp_sink(
+        busy: usize,
+    ) -> (
+        u16,
+        std::sync::mpsc::Receiver<String>,
+        std::thread::JoinHandle<()>,
+    ) {
+        use std::io::{BufRead, Write};
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let handle = std::thread::spawn(move || {
+            for (connection, stream) in listener.incoming().enumerate() {
+                let mut stream = stream.unwrap();
+                if connection < busy {
+                    stream.write_all(b"421 busy\r\n").unwrap();
+                    continue;
+                }
+                stream.write_all(b"220 localhost ESMTP\r\n").unwrap();
+                let mut reader = std::io::BufReader::new(stream.try_clone().unwrap());
+                let mut data = String::new();
+                let mut in_data = false;
+                loop {
+                    let mut line = String::new();
+                    if reader.read_line(&mut line).unwrap() == 0 {
+                        return;
+                    }
+                    if in_data {
+                        if line == ".\r\n" {
+                            in_data = false;
+                            sender.send(data.clone()).unwrap();
+                            stream.write_all(b"250 queued\r\n").unwrap();
+                        } else {
+                            data.push_str(&line);
+                        }
+                        continue;
+                    }
+                    let command = line.get(..4).unwrap_or("").to_uppercase();
+                    match command.as_str() {
+                        "DATA" => {
+                            in_data = true;
+                            stream.write_all(b"354 go ahead\r\n").unwrap();
+                        }
+                        "QUIT" => {
+                            stream.write_all(b"221 bye\r\n").unwrap();
+                            return;
+                        }
+                        _ => stream.write_all(b"250 ok\r\n").unwrap(),
+                    }
+                }
+            }
+        });
+        (port, receiver, handle)
+    }
+
+    fn configuration(port: u16) -> SmtpConfiguration {
+        SmtpConfiguration {
+            host: String::from("127.0.0.1"),
+            port: Some(port),
+            starttls: Some(false),
+            username: None,
+            password: None,
+            from: String::from("ci@example.com"),
+            to: vec![String::from("team@example.com")],
+        }
+    }
+
+    #[test]
+    fn can_send_email() {
+        let (port, emails, handle) = smtp_sink(1);
+        let email = Email {
+            subject: String::from("[Failed] ci/unit tests #42"),
+            text: String::from("tests are red"),
+            html: String::from("<p>tests are red</p>"),
+        };
+        let client = crate::http::Client::new().backoff(std::time::Duration::from_millis(1));
+        send(&client, &configuration(port), &email).unwrap();
+        handle.join().unwrap();
+
+        let received = emails.recv().unwrap();
+        assert!(received.contains("Subject: [Failed] ci/unit tests #42"));
+        assert!(received.contains("To: team@example.com"));
+        assert!(received.contains("tests are red"));
+        assert!(received.contains("<p>tests are red</p>"));
+    }
+
+    #[test]
+    fn can_escape_html() {
+        assert_eq!(
+            escape_html(r#"<a href="x">fish & chips</a>"#),
+            "&lt;a href=&quot;x&quot;&gt;fish &amp; chips&lt;/a&gt;"
+        );
+    }
+}
@@ -0,0 +1,135 @@
+use serde::Serialize;
+use serde_json::json;
+
+use super::{formatted_build_info_from_params, Message};
+use crate::OutParams;
+use concourse_resource::BuildMetadata;
+
+#[derive(Serialize, Debug)]
+pub struct GoogleChatMessage {
+    #[serde(rename = "cardsV2")]
+    cards_v2: Vec<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    thread: Option<Thread>,
+}
+
+#[derive(Serialize, Debug)]
+struct Thread {
+    #[serde(rename = "threadKey")]
+    thread_key: String,
+}
+
+impl Message {
+    pub(crate) fn into_google_chat_message(
+        self,
+        build_metadata: BuildMetadata,
+        params: &OutParams,
+        thread_key: Option<&String>,
+    ) -> GoogleChatMessage {
+        let formatted_build_info = formatted_build_info_from_params(&build_metadata);
+        let thread_key = match thread_key {
+            Some(thread_key) => crate::interpolation::interpolate(thread_key, |variable| {
+                super::build_variable(variable, &build_metadata)
+            }),
+            None => formatted_build_info.job_name.clone(),
+        };
+        let content = formatted_build_info.content(
+            self.text,
+            params,
+            formatted_build_info.build_name.clone(),
+        );
+
+        let mut widgets = vec![];
+        if let Some(text) = content.text {
+            widgets.push(json!({ "textParagraph": { "text": text } }));
+        }
+        for (name, value) in content.fields.unwrap_or_default() {
+            widgets.push(json!({ "decoratedText": { "topLabel": name, "text": value } }));
+        }
+        if let Some(build_url) = formatted_build_info.build_url {
+            widgets.push(json!({
+                "buttonList": {
+                    "buttons": [{
+                        "text": "View build",
+                        "onClick": { "openLink": { "url": build_url } },
+                    }],
+                },
+            }));
+        }
+
+        let mut card = json!({
+            "header": {
+                "title": content.title,
+                "subtitle": params.alert_type.message(),
+                "imageUrl": self.icon_url,
+                "imageType": "CIRCLE",
+            },
+        });
+        if !widgets.is_empty() {
+            card["sections"] = json!([{ "widgets": widgets }]);
+        }
+
+        GoogleChatMessage {
+            cards_v2: vec![json!({ "cardId": "concourse-build", "card": card })],
+            thread: Some(Thread { thread_key }),
+        }
+    }
+}
+
+pub(crate) fn send<T: Serialize>(
+    client: &crate::http::Client,
+    url: &str,
+    message: &T,
+) -> Result<super::Sent, String> {
+    let mut url = reqwest::Url::parse(url).map_err(|err| format!("{}", err))?;
+    // without it, Google Chat ignores `threadKey` and starts a new thread for each message
+    if !url
+        .query_pairs()
+        .any(|(key, _)| key == "messageReplyOption")
+    {
+        url.query_pairs_mut()
+            .append_pair("messageReplyOption", "REPLY_MESSAGE_FALLBACK_TO_NEW_THREAD");
+    }
+    super::send_to_webhook(client, url.as_str(), message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::tests::{build_metadata, out_params};
+
+    #[test]
+    fn can_render_google_chat_message() {
+        let params = out_params(json!({ "alert_type": "failed", "message": "tests are red" }));
+        let message = Message::new(
+            &params,
+            "",
+            &build_metadata(),
+            &crate::BuildContext::default(),
+        );
+        let message =
+            serde_json::to_value(message.into_google_chat_message(build_metadata(), &params, None))
+                .unwrap();
+        assert_eq!(message["thread"]["threadKey"], "ci/unit tests");
+        let card = &message["cardsV2"][0]["card"];
+        assert_eq!(card["header"]["subtitle"], "Failed");
+        assert_eq!(
+            card["sections"][0]["widgets"][0]["textParagraph"]["text"],
+            "tests are red"
+        );
+
+        let message = Message::new(
+            &params,
+            "",
+            &build_metadata(),
+            &crate::BuildContext::default(),
+        );
+        let message = serde_json::to_value(message.into_google_chat_message(
+            build_metadata(),
+            &params,
+            Some(&String::from("${BUILD_PIPELINE_NAME}")),
+        ))
+        .unwrap();
+        assert_eq!(message["thread"]["threadKey"], "ci");
+    }
+}
@@ -0,0 +1,131 @@
+use serde::Serialize;
+
+use super::{formatted_build_info_from_params, Message};
+use crate::OutParams;
+use concourse_resource::BuildMetadata;
+
+#[derive(Serialize, Debug)]
+pub struct MattermostMessage {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    channel: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    username: Option<String>,
+    icon_url: String,
+    attachments: Vec<Attachment>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    props: Option<Props>,
+}
+
+#[derive(Serialize, Debug)]
+struct Attachment {
+    fallback: String,
+    color: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    author_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    title: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    title_link: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    text: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    fields: Vec<Field>,
+}
+
+#[derive(Serialize, Debug)]
+struct Field {
+    title: String,
+    value: String,
+    short: bool,
+}
+
+#[derive(Serialize, Debug)]
+struct Props {
+    card: String,
+}
+
+impl Message {
+    pub(crate) fn into_mattermost_message(
+        self,
+        build_metadata: BuildMetadata,
+        params: &OutParams,
+        username: Option<&String>,
+    ) -> MattermostMessage {
+        let mut formatted_build_info = formatted_build_info_from_params(&build_metadata);
+        if let Some(build_url) = formatted_build_info.build_url.as_ref() {
+            formatted_build_info.build_number =
+                format!("[{}]({})", formatted_build_info.build_number, build_url);
+        }
+        let title = formatted_build_info.title(params);
+        let content = formatted_build_info.content(self.text, params, title.clone());
+        MattermostMessage {
+            channel: params.channel.clone(),
+            username: username.cloned(),
+            icon_url: self.icon_url,
+            attachments: vec![Attachment {
+                fallback: title,
+                color: self.color,
+                author_name: match params.mode {
+                    crate::Mode::Concise => None,
+                    crate::Mode::Normal | crate::Mode::NormalWithInfo => {
+                        Some(String::from(params.alert_type.message()))
+                    }
+                },
+                title: Some(content.title),
+                title_link: formatted_build_info.build_url,
+                text: content.text,
+                fields: content
+                    .fields
+                    .iter()
+                    .flatten()
+                    .map(|(title, value)| Field {
+                        title: title.clone(),
+                        value: value.clone(),
+                        short: true,
+                    })
+                    .collect(),
+            }],
+            props: content.fields.map(|fields| Props {
+                card: fields
+                    .iter()
+                    .map(|(title, value)| format!("**{}**: {}", title, value))
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::message::tests::{build_metadata, out_params};
+    use crate::message::Message;
+
+    #[test]
+    fn can_render_mattermost_message() {
+        let params = out_params(serde_json::json!({
+            "alert_type": "success",
+            "channel": "town-square",
+        }));
+        let message = Message::new(
+            &params,
+            "",
+            &build_metadata(),
+            &crate::BuildContext::default(),
+        );
+        let message = serde_json::to_value(message.into_mattermost_message(
+            build_metadata(),
+            &params,
+            Some(&String::from("ci")),
+        ))
+        .unwrap();
+        assert_eq!(message["channel"], "town-square");
+        assert_eq!(message["username"], "ci");
+        assert_eq!(message["attachments"][0]["author_name"], "Success");
+        assert_eq!(message["attachments"][0]["fields"][0]["title"], "Job");
+        assert!(message["props"]["card"]
+            .as_str()
+            .unwrap()
+            .starts_with("**Job**: ci/unit tests"));
+    }
+}
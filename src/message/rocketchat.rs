@@ -0,0 +1,138 @@
+use serde::{Deserialize, Serialize};
+
+use super::{formatted_build_info_from_params, Message};
+use crate::OutParams;
+use concourse_resource::BuildMetadata;
+
+#[derive(Serialize, Debug)]
+pub struct RocketChatMessage {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    channel: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    alias: Option<String>,
+    avatar: String,
+    attachments: Vec<Attachment>,
+}
+
+#[derive(Serialize, Debug)]
+struct Attachment {
+    color: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    author_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    author_icon: Option<String>,
+    title: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    title_link: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    text: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    fields: Vec<Field>,
+}
+
+#[derive(Serialize, Debug)]
+struct Field {
+    title: String,
+    value: String,
+    short: bool,
+}
+
+#[derive(Deserialize, Debug)]
+struct Response {
+    success: bool,
+    error: Option<String>,
+}
+
+impl Message {
+    pub(crate) fn into_rocketchat_message(
+        self,
+        build_metadata: BuildMetadata,
+        params: &OutParams,
+        username: Option<&String>,
+    ) -> RocketChatMessage {
+        let formatted_build_info = formatted_build_info_from_params(&build_metadata);
+        let content = formatted_build_info.content(
+            self.text,
+            params,
+            formatted_build_info.build_name.clone(),
+        );
+        RocketChatMessage {
+            channel: params.channel.clone(),
+            alias: username.cloned(),
+            avatar: self.icon_url.clone(),
+            attachments: vec![Attachment {
+                color: self.color,
+                author_name: match params.mode {
+                    crate::Mode::Concise => None,
+                    crate::Mode::Normal | crate::Mode::NormalWithInfo => {
+                        Some(String::from(params.alert_type.message()))
+                    }
+                },
+                author_icon: match params.mode {
+                    crate::Mode::Concise => None,
+                    crate::Mode::Normal | crate::Mode::NormalWithInfo => Some(self.icon_url),
+                },
+                title: content.title,
+                title_link: formatted_build_info.build_url,
+                text: content.text,
+                fields: content
+                    .fields
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|(title, value)| Field {
+                        title,
+                        value,
+                        short: true,
+                    })
+                    .collect(),
+            }],
+        }
+    }
+}
+
+pub(crate) fn send<T: Serialize>(
+    client: &crate::http::Client,
+    url: &str,
+    message: &T,
+) -> Result<super::Sent, String> {
+    let response = serde_json::from_str::<Response>(&client.post_json(url, None, message)?)
+        .map_err(|err| format!("invalid response from Rocket.Chat: {}", err))?;
+    if response.success {
+        Ok(super::Sent::default())
+    } else {
+        Err(response
+            .error
+            .unwrap_or_else(|| String::from("unknown error from Rocket.Chat")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::tests::{build_metadata, out_params};
+
+    #[test]
+    fn can_render_rocketchat_message() {
+        let params = out_params(serde_json::json!({
+            "alert_type": "success",
+            "channel": "#general",
+        }));
+        let message = Message::new(
+            &params,
+            "",
+            &build_metadata(),
+            &crate::BuildContext::default(),
+        );
+        let message = serde_json::to_value(message.into_rocketchat_message(
+            build_metadata(),
+            &params,
+            Some(&String::from("ci")),
+        ))
+        .unwrap();
+        assert_eq!(message["channel"], "#general");
+        assert_eq!(message["alias"], "ci");
+        assert_eq!(message["avatar"], message["attachments"][0]["author_icon"]);
+        assert_eq!(message["attachments"][0]["author_name"], "Success");
+        assert_eq!(message["attachments"][0]["fields"][1]["title"], "Build");
+    }
+}
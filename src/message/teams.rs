@@ -0,0 +1,137 @@
+use serde::Serialize;
+
+use super::{formatted_build_info_from_params, Message};
+use crate::OutParams;
+use concourse_resource::BuildMetadata;
+
+#[derive(Serialize, Debug)]
+pub struct MessageCard {
+    #[serde(rename = "@type")]
+    card_type: &'static str,
+    #[serde(rename = "@context")]
+    context: &'static str,
+    #[serde(rename = "themeColor")]
+    theme_color: String,
+    summary: String,
+    sections: Vec<Section>,
+    #[serde(rename = "potentialAction", skip_serializing_if = "Vec::is_empty")]
+    potential_action: Vec<OpenUri>,
+}
+
+#[derive(Serialize, Debug)]
+struct Section {
+    #[serde(rename = "activityTitle")]
+    activity_title: String,
+    #[serde(rename = "activityImage")]
+    activity_image: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    text: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    facts: Vec<Fact>,
+    markdown: bool,
+}
+
+#[derive(Serialize, Debug)]
+struct Fact {
+    name: String,
+    value: String,
+}
+
+#[derive(Serialize, Debug)]
+struct OpenUri {
+    #[serde(rename = "@type")]
+    action_type: &'static str,
+    name: &'static str,
+    targets: Vec<Target>,
+}
+
+#[derive(Serialize, Debug)]
+struct Target {
+    os: &'static str,
+    uri: String,
+}
+
+impl Message {
+    pub(crate) fn into_teams_message(
+        self,
+        build_metadata: BuildMetadata,
+        params: &OutParams,
+    ) -> MessageCard {
+        let formatted_build_info = formatted_build_info_from_params(&build_metadata);
+        let title = formatted_build_info.title(params);
+        let content = formatted_build_info.content(self.text, params, title.clone());
+        MessageCard {
+            card_type: "MessageCard",
+            context: "https://schema.org/extensions",
+            theme_color: self.color.trim_start_matches('#').to_string(),
+            summary: title.clone(),
+            sections: vec![Section {
+                activity_title: content.title,
+                activity_image: self.icon_url,
+                text: content.text,
+                facts: content
+                    .fields
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|(name, value)| Fact { name, value })
+                    .collect(),
+                markdown: true,
+            }],
+            potential_action: formatted_build_info
+                .build_url
+                .map(|build_url| OpenUri {
+                    action_type: "OpenUri",
+                    name: "View build",
+                    targets: vec![Target {
+                        os: "default",
+                        uri: build_url,
+                    }],
+                })
+                .into_iter()
+                .collect(),
+        }
+    }
+}
+
+pub(crate) fn send<T: Serialize>(
+    client: &crate::http::Client,
+    url: &str,
+    message: &T,
+) -> Result<super::Sent, String> {
+    let response = client.post_json(url, None, message)?;
+    // Teams answers `1` on success, and an error message with a success status otherwise
+    match response.trim() {
+        "" | "1" => Ok(super::Sent::default()),
+        error => Err(String::from(error)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::message::tests::{build_metadata, out_params};
+    use crate::message::Message;
+
+    #[test]
+    fn can_render_message_card() {
+        let params = out_params(serde_json::json!({
+            "alert_type": "failed",
+            "message": "tests are red",
+        }));
+        let message = Message::new(
+            &params,
+            "",
+            &build_metadata(),
+            &crate::BuildContext::default(),
+        );
+        let card =
+            serde_json::to_value(message.into_teams_message(build_metadata(), &params)).unwrap();
+        assert_eq!(card["@type"], "MessageCard");
+        assert!(!card["themeColor"].as_str().unwrap().starts_with('#'));
+        assert_eq!(card["sections"][0]["text"], "tests are red");
+        assert_eq!(card["sections"][0]["facts"][0]["name"], "Job");
+        assert_eq!(
+            card["potentialAction"][0]["targets"][0]["uri"],
+            "https://ci.example.com/teams/main/pipelines/ci/jobs/unit%20tests/builds/42"
+        );
+    }
+}
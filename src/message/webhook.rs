@@ -0,0 +1,183 @@
+use std::collections::HashMap;
+
+use serde::Serialize;
+
+use super::{formatted_build_info_from_params, Message};
+use crate::{AlertType, OutParams};
+use concourse_resource::BuildMetadata;
+
+#[derive(Serialize, Debug)]
+struct Context<'a> {
+    alert_type: &'a AlertType,
+    alert_message: &'static str,
+    color: String,
+    icon_url: String,
+    text: Option<String>,
+    channel: Option<&'a String>,
+    job_name: String,
+    build_name: String,
+    build_number: String,
+    build_url: Option<String>,
+    team_name: &'a str,
+    pipeline_name: Option<&'a str>,
+}
+
+impl Message {
+    /// Render the JSON body of the webhook from `body_template`, or serialize the whole context if
+    /// there is none.
+    pub(crate) fn into_webhook_body(
+        self,
+        build_metadata: BuildMetadata,
+        params: &OutParams,
+        body_template: Option<&String>,
+    ) -> Result<serde_json::Value, String> {
+        let formatted_build_info = formatted_build_info_from_params(&build_metadata);
+        let context = Context {
+            alert_type: &params.alert_type,
+            alert_message: params.alert_type.message(),
+            color: self.color,
+            icon_url: self.icon_url,
+            text: self.text,
+            channel: params.channel.as_ref(),
+            job_name: formatted_build_info.job_name,
+            build_name: formatted_build_info.build_name,
+            build_number: formatted_build_info.build_number,
+            build_url: formatted_build_info.build_url,
+            team_name: &build_metadata.team_name,
+            pipeline_name: build_metadata.pipeline_name.as_deref(),
+        };
+        match body_template {
+            Some(body_template) => {
+                let body = crate::template::render_json(body_template, &context)?;
+                serde_json::from_str(&body)
+                    .map_err(|err| format!("body template is not valid JSON: {}", err))
+            }
+            None => serde_json::to_value(&context).map_err(|err| format!("{}", err)),
+        }
+    }
+}
+
+pub(crate) fn send<T: Serialize>(
+    client: &crate::http::Client,
+    url: &str,
+    method: Option<&String>,
+    headers: &HashMap<String, String>,
+    message: &T,
+) -> Result<super::Sent, String> {
+    let method = method
+        .map(|method| {
+            reqwest::Method::from_bytes(method.to_uppercase().as_bytes())
+                .map_err(|_| format!("invalid HTTP method {}", method))
+        })
+        .transpose()?
+        .unwrap_or(reqwest::Method::POST);
+    let body = serde_json::to_vec(message).map_err(|err| format!("{}", err))?;
+    client.send(method, url, &request_headers(headers), body)?;
+    Ok(super::Sent::default())
+}
+
+/// Headers of the request, with a JSON `Content-Type` unless one is configured.
+fn request_headers(headers: &HashMap<String, String>) -> Vec<(&str, &str)> {
+    let mut request_headers = headers
+        .iter()
+        .map(|(name, value)| (name.as_str(), value.as_str()))
+        .collect::<Vec<_>>();
+    if !headers
+        .keys()
+        .any(|name| name.eq_ignore_ascii_case("content-type"))
+    {
+        request_headers.push(("Content-Type", "application/json"));
+    }
+    request_headers
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::tests::{build_metadata, out_params};
+
+    fn message(params: &OutParams) -> Message {
+        Message::new(
+            params,
+            "",
+            &build_metadata(),
+            &crate::BuildContext::default(),
+        )
+    }
+
+    #[test]
+    fn can_render_webhook_body_template() {
+        let params = out_params(serde_json::json!({
+            "alert_type": "failed",
+            "message": "tests \"failed\"\nsee logs",
+        }));
+        let body_template = String::from(
+            r#"{"summary": "{{build_name}} {{alert_message}}", "details": "{{text}}", "link": "{{build_url}}"}"#,
+        );
+        assert_eq!(
+            message(&params).into_webhook_body(build_metadata(), &params, Some(&body_template)),
+            Ok(serde_json::json!({
+                "summary": "ci/unit tests #42 Failed",
+                "details": "tests \"failed\"\nsee logs",
+                "link": "https://ci.example.com/teams/main/pipelines/ci/jobs/unit%20tests/builds/42",
+            }))
+        );
+
+        let body_template = String::from(r#"{"summary": {{build_name}}}"#);
+        assert!(message(&params)
+            .into_webhook_body(build_metadata(), &params, Some(&body_template))
+            .unwrap_err()
+            .starts_with("body template is not valid JSON"));
+    }
+
+    #[test]
+    fn can_send_whole_context_without_body_template() {
+        let params = out_params(serde_json::json!({
+            "alert_type": "success",
+            "channel": "builds",
+        }));
+        assert_eq!(
+            message(&params).into_webhook_body(build_metadata(), &params, None),
+            Ok(serde_json::json!({
+                "alert_type": "success",
+                "alert_message": "Success",
+                "color": "#11c560",
+                "icon_url": "https://ci.concourse-ci.org/public/images/favicon-succeeded.png",
+                "text": null,
+                "channel": "builds",
+                "job_name": "ci/unit tests",
+                "build_name": "ci/unit tests #42",
+                "build_number": "#42",
+                "build_url": "https://ci.example.com/teams/main/pipelines/ci/jobs/unit%20tests/builds/42",
+                "team_name": "main",
+                "pipeline_name": "ci",
+            }))
+        );
+    }
+
+    #[test]
+    fn can_replace_content_type() {
+        let mut headers = HashMap::new();
+        headers.insert(String::from("Authorization"), String::from("Bearer token"));
+        let mut sent = request_headers(&headers);
+        sent.sort();
+        assert_eq!(
+            sent,
+            vec![
+                ("Authorization", "Bearer token"),
+                ("Content-Type", "application/json")
+            ]
+        );
+
+        headers.insert(String::from("content-type"), String::from("text/plain"));
+        let mut sent = request_headers(&headers);
+        sent.sort();
+        assert_eq!(
+            sent,
+            vec![
+                ("Authorization", "Bearer token"),
+                ("content-type", "text/plain")
+            ]
+        );
+    }
+}
@@ -0,0 +1,105 @@
+use serde::Deserialize;
+
+use crate::AlertType;
+use concourse_resource::BuildMetadata;
+
+/// Send notifications matching the alert types and the pipeline and job patterns to `channel`.
+#[derive(Deserialize, Debug)]
+pub(crate) struct Route {
+    alert_types: Option<Vec<AlertType>>,
+    pipeline: Option<String>,
+    job: Option<String>,
+    pub(crate) channel: String,
+}
+
+impl Route {
+    pub(crate) fn matches(&self, alert_type: &AlertType, build_metadata: &BuildMetadata) -> bool {
+        let matches_pattern = |pattern: Option<&String>, value: Option<&String>| match pattern {
+            None => true,
+            Some(pattern) => value
+                .map(|value| glob_matches(pattern, value))
+                .unwrap_or(false),
+        };
+        self.alert_types
+            .as_ref()
+            .map(|alert_types| alert_types.contains(alert_type))
+            .unwrap_or(true)
+            && matches_pattern(
+                self.pipeline.as_ref(),
+                build_metadata.pipeline_name.as_ref(),
+            )
+            && matches_pattern(self.job.as_ref(), build_metadata.job_name.as_ref())
+    }
+}
+
+/// Match `value` against `pattern`, where `*` matches any sequence of characters and `?` a single
+/// character.
+fn glob_matches(pattern: &str, value: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let value = value.chars().collect::<Vec<_>>();
+    let (mut p, mut v) = (0, 0);
+    let mut backtrack = None;
+    while v < value.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, v));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == value[v] => {
+                p += 1;
+                v += 1;
+            }
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    p = star + 1;
+                    v = matched + 1;
+                    backtrack = Some((star, matched + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::tests::build_metadata;
+
+    #[test]
+    fn can_match_route() {
+        let route = |route: serde_json::Value| serde_json::from_value::<Route>(route).unwrap();
+
+        let any = route(serde_json::json!({"channel": "#ci"}));
+        assert!(any.matches(&AlertType::Success, &build_metadata()));
+
+        let failures = route(serde_json::json!({
+            "alert_types": ["failed", "errored"],
+            "pipeline": "c?",
+            "job": "unit *",
+            "channel": "#ci",
+        }));
+        assert!(failures.matches(&AlertType::Failed, &build_metadata()));
+        assert!(!failures.matches(&AlertType::Success, &build_metadata()));
+
+        let mut other_job = build_metadata();
+        other_job.job_name = Some(String::from("deploy"));
+        assert!(!failures.matches(&AlertType::Failed, &other_job));
+
+        let mut no_pipeline = build_metadata();
+        no_pipeline.pipeline_name = None;
+        assert!(!failures.matches(&AlertType::Failed, &no_pipeline));
+    }
+
+    #[test]
+    fn can_match_globs() {
+        assert!(glob_matches("deploy-*", "deploy-prod"));
+        assert!(glob_matches("*", ""));
+        assert!(glob_matches("*-prod", "deploy-eu-prod"));
+        assert!(glob_matches("build-?", "build-1"));
+        assert!(glob_matches("a*b*c", "aXbYbZc"));
+        assert!(!glob_matches("deploy-*", "test-deploy"));
+        assert!(!glob_matches("build-?", "build-12"));
+    }
+}
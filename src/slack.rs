@@ -0,0 +1,186 @@
+use serde::{Deserialize, Serialize};
+
+use crate::message::Sent;
+
+const POST_MESSAGE_URL: &str = "https://slack.com/api/chat.postMessage";
+const UPDATE_URL: &str = "https://slack.com/api/chat.update";
+const LOOKUP_BY_EMAIL_URL: &str = "https://slack.com/api/users.lookupByEmail";
+
+#[derive(Deserialize, Debug)]
+struct ApiResponse {
+    ok: bool,
+    error: Option<String>,
+    ts: Option<String>,
+    channel: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct LookupResponse {
+    ok: bool,
+    error: Option<String>,
+    user: Option<User>,
+}
+
+#[derive(Deserialize, Debug)]
+struct User {
+    id: String,
+}
+
+/// Identifies a message already posted to Slack, as written by `in` to its output directory.
+#[derive(Debug, PartialEq, Clone)]
+pub(crate) struct MessageRef {
+    pub(crate) channel_id: String,
+    pub(crate) ts: String,
+    /// Position of the destination that posted the message in the destinations of the source
+    pub(crate) destination: usize,
+}
+
+impl MessageRef {
+    pub(crate) fn read_from(path: &std::path::Path) -> Result<Self, String> {
+        let read = |file: &str| {
+            std::fs::read_to_string(path.join(file))
+                .map(|content| content.trim().to_string())
+                .map_err(|err| format!("error reading {}: {}", path.join(file).display(), err))
+        };
+        Ok(Self {
+            channel_id: read("channel_id")?,
+            ts: read("ts")?,
+            // messages posted before destinations were recorded come from the first one
+            destination: read("destination")
+                .ok()
+                .and_then(|destination| destination.parse().ok())
+                .unwrap_or(0),
+        })
+    }
+
+    pub(crate) fn write_to(&self, path: &std::path::Path) -> std::io::Result<()> {
+        std::fs::write(path.join("channel_id"), &self.channel_id)?;
+        std::fs::write(path.join("ts"), &self.ts)?;
+        std::fs::write(path.join("destination"), self.destination.to_string())?;
+        Ok(())
+    }
+}
+
+pub(crate) fn post_message<T: Serialize>(
+    client: &crate::http::Client,
+    token: &str,
+    message: &T,
+) -> Result<Sent, String> {
+    parse_api_response(&client.post_json(POST_MESSAGE_URL, Some(token), message)?)
+}
+
+pub(crate) fn update_message<T: Serialize>(
+    client: &crate::http::Client,
+    token: &str,
+    previous: &MessageRef,
+    message: &T,
+) -> Result<Sent, String> {
+    parse_api_response(&client.post_json(
+        UPDATE_URL,
+        Some(token),
+        &update_body(previous, message)?,
+    )?)
+}
+
+/// `chat.update` identifies the message to replace with its `channel` and `ts`.
+fn update_body<T: Serialize>(
+    previous: &MessageRef,
+    message: &T,
+) -> Result<serde_json::Value, String> {
+    let mut body = serde_json::to_value(message).map_err(|err| format!("{}", err))?;
+    body["channel"] = serde_json::Value::from(previous.channel_id.as_str());
+    body["ts"] = serde_json::Value::from(previous.ts.as_str());
+    Ok(body)
+}
+
+/// Find the ID of the Slack user with this email, requires the `users:read.email` scope.
+pub(crate) fn lookup_user_by_email(
+    client: &crate::http::Client,
+    token: &str,
+    email: &str,
+) -> Result<String, String> {
+    let mut url = reqwest::Url::parse(LOOKUP_BY_EMAIL_URL).map_err(|err| format!("{}", err))?;
+    url.query_pairs_mut().append_pair("email", email);
+    let authorization = format!("Bearer {}", token);
+    let response = client.send(
+        reqwest::Method::GET,
+        url.as_str(),
+        &[("Authorization", &authorization)],
+        vec![],
+    )?;
+    let response = serde_json::from_str::<LookupResponse>(&response)
+        .map_err(|err| format!("invalid response from Slack API: {}", err))?;
+    match (response.ok, response.user) {
+        (true, Some(user)) => Ok(user.id),
+        _ => Err(response
+            .error
+            .unwrap_or_else(|| String::from("unknown error from Slack API"))),
+    }
+}
+
+fn parse_api_response(response: &str) -> Result<Sent, String> {
+    let response = serde_json::from_str::<ApiResponse>(response)
+        .map_err(|err| format!("invalid response from Slack API: {}", err))?;
+    if response.ok {
+        Ok(Sent {
+            ts: response.ts,
+            channel_id: response.channel,
+        })
+    } else {
+        Err(response
+            .error
+            .unwrap_or_else(|| String::from("unknown error from Slack API")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn can_parse_api_response() {
+        let sent =
+            parse_api_response(r#"{"ok":true,"channel":"C123","ts":"1503435956.000247"}"#).unwrap();
+        assert_eq!(sent.ts.as_deref(), Some("1503435956.000247"));
+        assert_eq!(sent.channel_id.as_deref(), Some("C123"));
+
+        assert_eq!(
+            parse_api_response(r#"{"ok":false,"error":"channel_not_found"}"#).unwrap_err(),
+            "channel_not_found"
+        );
+    }
+
+    #[test]
+    fn can_update_referenced_message() {
+        let previous = MessageRef {
+            channel_id: String::from("C123"),
+            ts: String::from("1503435956.000247"),
+            destination: 0,
+        };
+        let body = update_body(
+            &previous,
+            &serde_json::json!({"channel": "#builds", "text": "hello"}),
+        )
+        .unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({"channel": "C123", "ts": "1503435956.000247", "text": "hello"})
+        );
+    }
+
+    #[test]
+    fn can_write_and_read_message_ref() {
+        let path = std::env::temp_dir().join("slack-notifier-message-ref");
+        std::fs::create_dir_all(&path).unwrap();
+        let message_ref = MessageRef {
+            channel_id: String::from("C123"),
+            ts: String::from("1503435956.000247"),
+            destination: 1,
+        };
+        message_ref.write_to(&path).unwrap();
+        assert_eq!(MessageRef::read_from(&path).unwrap(), message_ref);
+
+        std::fs::remove_file(path.join("destination")).unwrap();
+        assert_eq!(MessageRef::read_from(&path).unwrap().destination, 0);
+    }
+}
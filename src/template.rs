@@ -0,0 +1,141 @@
+use std::collections::HashMap;
+
+use serde::Serialize;
+
+use crate::{concourse, AlertType};
+use concourse_resource::BuildMetadata;
+
+#[derive(Serialize, Debug)]
+struct BuildContext<'a> {
+    id: &'a str,
+    name: Option<&'a str>,
+    job_name: Option<&'a str>,
+    pipeline_name: Option<&'a str>,
+    team_name: &'a str,
+    atc_external_url: &'a str,
+    url: Option<String>,
+    start_time: Option<i64>,
+    created_by: Option<&'a str>,
+    rerun_number: Option<u32>,
+    rerun_of: Option<&'a concourse::RerunOf>,
+}
+
+#[derive(Serialize, Debug)]
+pub(crate) struct Context<'a> {
+    build: BuildContext<'a>,
+    alert_type: &'a AlertType,
+    previous_build: Option<&'a concourse::Build>,
+    streak: Option<&'a concourse::Streak>,
+    data: HashMap<&'a str, serde_json::Value>,
+}
+
+impl<'a> Context<'a> {
+    pub(crate) fn new(
+        build_metadata: &'a BuildMetadata,
+        build_url: Option<String>,
+        alert_type: &'a AlertType,
+        current_build: Option<&'a concourse::Build>,
+        previous_build: Option<&'a concourse::Build>,
+        streak: Option<&'a concourse::Streak>,
+    ) -> Self {
+        Self {
+            build: BuildContext {
+                id: &build_metadata.id,
+                name: build_metadata.name.as_deref(),
+                job_name: build_metadata.job_name.as_deref(),
+                pipeline_name: build_metadata.pipeline_name.as_deref(),
+                team_name: &build_metadata.team_name,
+                atc_external_url: &build_metadata.atc_external_url,
+                url: build_url,
+                start_time: current_build.and_then(|build| build.start_time),
+                created_by: current_build.and_then(|build| build.created_by.as_deref()),
+                rerun_number: current_build.and_then(|build| build.rerun_number),
+                rerun_of: current_build.and_then(|build| build.rerun_of.as_ref()),
+            },
+            alert_type,
+            previous_build,
+            streak,
+            data: HashMap::new(),
+        }
+    }
+
+    /// Load JSON or YAML files from the input directory, depending on their extension, into `data`.
+    pub(crate) fn load_data(
+        mut self,
+        input_path: &str,
+        files: &'a HashMap<String, String>,
+    ) -> Result<Self, String> {
+        for (name, file) in files {
+            let mut path = std::path::PathBuf::new();
+            path.push(input_path);
+            path.push(file);
+            let content = std::fs::read_to_string(&path)
+                .map_err(|err| format!("error reading file {}: {}", file, err))?;
+            let value = match path.extension().and_then(|extension| extension.to_str()) {
+                Some("yml") | Some("yaml") => {
+                    serde_yaml::from_str::<serde_json::Value>(&content)
+                        .map_err(|err| format!("error parsing file {}: {}", file, err))?
+                }
+                _ => serde_json::from_str::<serde_json::Value>(&content)
+                    .map_err(|err| format!("error parsing file {}: {}", file, err))?,
+            };
+            self.data.insert(name, value);
+        }
+        Ok(self)
+    }
+}
+
+pub(crate) fn render<T: Serialize>(template: &str, context: &T) -> Result<String, String> {
+    let mut handlebars = handlebars::Handlebars::new();
+    // messages are Slack mrkdwn, not HTML
+    handlebars.register_escape_fn(handlebars::no_escape);
+    handlebars
+        .render_template(template, context)
+        .map_err(|err| format!("error rendering template: {}", err))
+}
+
+/// Render a template producing JSON, values are escaped to be used inside JSON strings.
+pub(crate) fn render_json<T: Serialize>(template: &str, context: &T) -> Result<String, String> {
+    let mut handlebars = handlebars::Handlebars::new();
+    handlebars.register_escape_fn(|value| {
+        let escaped = serde_json::to_string(value).unwrap_or_else(|_| String::from("\"\""));
+        String::from(&escaped[1..escaped.len() - 1])
+    });
+    handlebars
+        .render_template(template, context)
+        .map_err(|err| format!("error rendering template: {}", err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn can_render_template() {
+        let context = serde_json::json!({
+            "build": {"job_name": "deploy", "name": "42"},
+            "alert_type": AlertType::Failed,
+            "previous_build": {"status": concourse::Status::Succeeded},
+            "data": {"services": [{"name": "api"}, {"name": "web"}]},
+        });
+
+        assert_eq!(
+            render(
+                "{{build.job_name}} #{{build.name}}{{#if (eq alert_type \"failed\")}} <!subteam^S123>{{/if}}{{#if (eq previous_build.status \"succeeded\")}}, was green{{/if}}: {{#each data.services}}{{name}} {{/each}}",
+                &context
+            )
+            .unwrap(),
+            "deploy #42 <!subteam^S123>, was green: api web "
+        );
+    }
+
+    #[test]
+    fn can_render_json_template() {
+        let context = serde_json::json!({ "text": "leave\nthen say \"hi\"" });
+
+        assert_eq!(
+            render_json(r#"{"message": "{{text}}"}"#, &context).unwrap(),
+            r#"{"message": "leave\nthen say \"hi\""}"#
+        );
+    }
+}